@@ -0,0 +1,23 @@
+//! Serializes the syntax and theme sets used for highlighting, so the
+//! worker only has to deserialize them once per isolate.
+
+use std::{env, path::Path};
+
+use syntect::dumps::dump_to_file;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+
+    dump_to_file(
+        &SyntaxSet::load_defaults_newlines(),
+        out_dir.join("syntaxes.packdump"),
+    )
+    .expect("couldn't dump syntax set");
+    dump_to_file(&ThemeSet::load_defaults(), out_dir.join("themes.themedump"))
+        .expect("couldn't dump theme set");
+}
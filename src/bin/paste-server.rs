@@ -0,0 +1,55 @@
+//! Self-hosted paste server, keeping pastes in SQLite or a directory tree
+//! instead of Workers KV. Configured from the environment:
+//!
+//! - `PASTE_STORE`: `sqlite:<file>` or `fs:<directory>`
+//! - `PASTE_LISTEN`: address to listen on, `127.0.0.1:8787` by default
+//! - `PASTE_TRUSTED_PROXIES`: comma-separated addresses of reverse proxies
+//!   whose `Host` and `X-Forwarded-*` headers are believed
+//! - every `PASTE_*` var the worker reads from `wrangler.toml`
+
+use anyhow::{bail, Context, Result};
+
+use rustwasm_cf_paste::{
+    config::Config,
+    server,
+    store::{FsPasteStore, SqlitePasteStore},
+};
+
+fn main() -> Result<()> {
+    let config = Config::from_lookup(|name| std::env::var(name).ok());
+    let options = server::Options {
+        listen: std::env::var("PASTE_LISTEN").unwrap_or_else(|_| "127.0.0.1:8787".to_string()),
+        trusted_proxies: std::env::var("PASTE_TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| {
+                addr.parse()
+                    .with_context(|| format!("invalid proxy address {addr}"))
+            })
+            .collect::<Result<_>>()?,
+    };
+    let listen = &options.listen;
+    let location = std::env::var("PASTE_STORE").unwrap_or_default();
+
+    match location.split_once(':') {
+        Some(("sqlite", path)) => {
+            let store = SqlitePasteStore::open(path)?;
+            eprintln!("serving pastes from {path} on http://{listen}");
+            server::run(&options, &store, &config, || purge(store.purge_expired()))
+        }
+        Some(("fs", path)) => {
+            let store = FsPasteStore::open(path)?;
+            eprintln!("serving pastes from {path} on http://{listen}");
+            server::run(&options, &store, &config, || purge(store.purge_expired()))
+        }
+        _ => bail!("set PASTE_STORE to sqlite:<file> or fs:<directory>"),
+    }
+}
+
+fn purge(purged: Result<usize>) {
+    if let Err(err) = purged {
+        eprintln!("error purging expired entries: {err:?}");
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+
+use crate::store::{PasteStore, PutOptions};
+
+pub fn chunk_key(id: &str, index: u32) -> String {
+    format!("chunk:{id}:{index}")
+}
+
+pub fn chunk_keys(id: &str, count: u32) -> Vec<String> {
+    (0..count).map(|index| chunk_key(id, index)).collect()
+}
+
+/// Splits `content` into pieces of at most `size` bytes, never cutting a
+/// UTF-8 character in half.
+pub fn split(content: &str, size: usize) -> Vec<&str> {
+    let size = size.max(4);
+    let mut chunks = Vec::new();
+    let mut rest = content;
+
+    while !rest.is_empty() {
+        let mut end = size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks
+}
+
+/// Stores `content` as numbered chunks next to the paste and returns how
+/// many were written. The paste record itself only keeps the count.
+pub async fn write(
+    store: &dyn PasteStore,
+    id: &str,
+    content: &str,
+    size: usize,
+    expires_at: Option<u64>,
+) -> Result<u32> {
+    let chunks = split(content, size);
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        store
+            .put(
+                &chunk_key(id, index as u32),
+                chunk.to_string(),
+                PutOptions {
+                    metadata: None,
+                    expires_at,
+                },
+            )
+            .await?;
+    }
+
+    Ok(chunks.len() as u32)
+}
+
+/// Reassembles a chunked paste. Only used where the whole document is
+/// needed at once; plain reads stream the chunks instead.
+pub async fn read_all(store: &dyn PasteStore, id: &str, count: u32) -> Result<String> {
+    let mut content = String::new();
+
+    for key in chunk_keys(id, count) {
+        match store.get(&key).await? {
+            Some(chunk) => content.push_str(&chunk.value),
+            None => return Err(anyhow!("missing {}", key)),
+        }
+    }
+
+    Ok(content)
+}
+
+pub async fn delete(store: &dyn PasteStore, id: &str, count: u32) -> Result<()> {
+    for key in chunk_keys(id, count) {
+        store.delete(&key).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_content_has_no_chunks() {
+        assert!(split("", 4).is_empty());
+    }
+
+    #[test]
+    fn exact_multiples_split_evenly() {
+        assert_eq!(split("0123456789", 5), ["01234", "56789"]);
+        assert_eq!(split("0123456789", 10), ["0123456789"]);
+        assert_eq!(split("0123456789", 11), ["0123456789"]);
+    }
+
+    #[test]
+    fn multibyte_characters_are_never_cut() {
+        // 1, 2, 3 and 4 byte characters, so the size falls inside each.
+        let content = "aé€😀";
+        let chunks = split(content, 4);
+        assert_eq!(chunks, ["aé", "€", "😀"]);
+        assert_eq!(chunks.concat(), content);
+
+        // Sizes below the longest UTF-8 character still make progress.
+        assert_eq!(split("😀😀", 1), ["😀", "😀"]);
+
+        let text = "héllo wörld ".repeat(100);
+        for size in 4..16 {
+            let chunks = split(&text, size);
+            assert!(chunks.iter().all(|chunk| chunk.len() <= size));
+            assert_eq!(chunks.concat(), text);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use worker::Env;
+
+use crate::{expiry, id::IdScheme, templates};
+
+/// Runtime settings read from the `[vars]` section of `wrangler.toml`.
+pub struct Config {
+    pub id_scheme: IdScheme,
+    pub id_length: Option<usize>,
+    pub id_alphabet: Option<String>,
+    /// Upper bound for paste lifetimes in seconds. `None` allows pastes
+    /// that never expire.
+    pub max_expiry: Option<u64>,
+    /// Pastes larger than this many bytes are split into chunks of this
+    /// size instead of being stored as a single value.
+    pub chunk_size: usize,
+    /// Largest paste accepted, in bytes.
+    pub max_paste_size: usize,
+    /// Largest request body accepted, in bytes. Defaults to room for a
+    /// `max_paste_size` paste even when urlencoding triples its size.
+    pub max_request_size: usize,
+    /// Theme id used when the visitor hasn't picked one, or `auto`.
+    pub theme: String,
+    /// Themes `auto` switches between.
+    pub light_theme: String,
+    pub dark_theme: String,
+    /// Public origin permalinks are built on, e.g.
+    /// `https://paste.priver.dev`. Defaults to the origin of each request.
+    pub base_url: Option<String>,
+    /// Page templates replacing the built-in ones, by template name, read
+    /// from `PASTE_TEMPLATE_<NAME>`.
+    pub templates: HashMap<String, String>,
+    /// Tokens allowed to reserve slugs ahead of publishing, from the
+    /// comma-separated `PASTE_OWNER_TOKENS`. Empty disables reservations
+    /// ahead of time.
+    pub owner_tokens: Vec<String>,
+    /// How long a slug stays reserved after its paste expires, or after it
+    /// was reserved, in seconds. `None` keeps reservations forever.
+    pub slug_reservation_ttl: Option<u64>,
+}
+
+/// Default for `PASTE_SLUG_RESERVATION_TTL`.
+const SLUG_RESERVATION_TTL: u64 = 30 * 24 * 60 * 60;
+
+impl Config {
+    pub fn from_env(env: &Env) -> Self {
+        Self::from_lookup(|name| env.var(name).ok().map(|var| var.to_string()))
+    }
+
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let max_paste_size = lookup("PASTE_MAX_SIZE")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(10 * 1024 * 1024);
+
+        Config {
+            id_scheme: lookup("PASTE_ID_SCHEME")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or_default(),
+            id_length: lookup("PASTE_ID_LENGTH").and_then(|value| value.parse().ok()),
+            id_alphabet: lookup("PASTE_ID_ALPHABET").filter(|value| !value.is_empty()),
+            max_expiry: lookup("PASTE_MAX_EXPIRY")
+                .and_then(|value| expiry::parse_duration(&value).ok())
+                .flatten(),
+            chunk_size: lookup("PASTE_CHUNK_SIZE")
+                .and_then(|value| value.parse().ok())
+                .filter(|size| *size > 0)
+                .unwrap_or(1024 * 1024),
+            max_paste_size,
+            max_request_size: lookup("PASTE_MAX_REQUEST_SIZE")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(max_paste_size * 3 + 64 * 1024),
+            theme: lookup("PASTE_THEME").unwrap_or_else(|| "solarized-dark".to_string()),
+            light_theme: lookup("PASTE_THEME_LIGHT")
+                .unwrap_or_else(|| "inspiredgithub".to_string()),
+            dark_theme: lookup("PASTE_THEME_DARK").unwrap_or_else(|| "solarized-dark".to_string()),
+            base_url: lookup("PASTE_BASE_URL")
+                .map(|value| value.trim().trim_end_matches('/').to_string())
+                .filter(|value| !value.is_empty()),
+            templates: templates::names()
+                .filter_map(|name| {
+                    lookup(&format!("PASTE_TEMPLATE_{}", name.to_ascii_uppercase()))
+                        .filter(|text| !text.is_empty())
+                        .map(|text| (name.to_string(), text))
+                })
+                .collect(),
+            owner_tokens: lookup("PASTE_OWNER_TOKENS")
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|token| !token.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            slug_reservation_ttl: match lookup("PASTE_SLUG_RESERVATION_TTL") {
+                Some(value) => expiry::parse_duration(&value).unwrap_or(Some(SLUG_RESERVATION_TTL)),
+                None => Some(SLUG_RESERVATION_TTL),
+            },
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::highlight::{self, syntax_set};
+
+/// Heuristics only look at the start of a paste.
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Substrings that hint at a language, keyed by the extension stored for
+/// it. A language needs two distinct hits to be picked.
+const HINTS: &[(&str, &[&str])] = &[
+    (
+        "rs",
+        &[
+            "fn main(",
+            "let mut ",
+            "impl ",
+            "pub fn ",
+            "use std::",
+            "#[derive(",
+            "-> Result<",
+        ],
+    ),
+    (
+        "py",
+        &[
+            "def ", "import ", "from ", "elif ", "self.", "print(", "__name__",
+        ],
+    ),
+    (
+        "js",
+        &[
+            "function ",
+            "const ",
+            "=> {",
+            "console.log(",
+            "require(",
+            "document.",
+            "let ",
+        ],
+    ),
+    (
+        "go",
+        &["package ", "func ", ":= ", "fmt.", "import (", "err != nil"],
+    ),
+    (
+        "c",
+        &[
+            "#include <",
+            "int main(",
+            "printf(",
+            "malloc(",
+            "->",
+            "sizeof(",
+        ],
+    ),
+    (
+        "java",
+        &[
+            "public class ",
+            "System.out",
+            "private ",
+            "public static void main",
+            "import java.",
+        ],
+    ),
+    (
+        "html",
+        &["<!DOCTYPE html", "<html", "<div", "<head>", "<body"],
+    ),
+    (
+        "sql",
+        &[
+            "SELECT ",
+            "FROM ",
+            "WHERE ",
+            "INSERT INTO",
+            "CREATE TABLE",
+            "JOIN ",
+        ],
+    ),
+    ("yaml", &["---\n", ":\n  ", "\n- ", "apiVersion:", "name: "]),
+    (
+        "sh",
+        &["echo ", "fi\n", "then\n", "export ", "$(", "done\n"],
+    ),
+];
+
+/// Guesses the language of a paste, as a token `find_syntax_by_extension`
+/// understands. The uploaded filename wins over the first line (shebangs,
+/// `<?php`, `<?xml` and the like), which wins over content heuristics.
+pub fn detect(content: &str, filename: Option<&str>) -> Option<String> {
+    filename
+        .and_then(from_filename)
+        .or_else(|| from_first_line(content))
+        .or_else(|| from_content(content))
+}
+
+fn from_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next()?;
+
+    highlight::resolve_language(name)
+}
+
+fn from_first_line(content: &str) -> Option<String> {
+    let line = content.lines().next()?;
+    let syntax = syntax_set().find_syntax_by_first_line(line)?;
+
+    syntax.file_extensions.first().cloned()
+}
+
+fn from_content(content: &str) -> Option<String> {
+    let mut end = content.len().min(SAMPLE_SIZE);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = end < content.len();
+    let sample = content[..end].trim_start();
+
+    if (sample.starts_with('{') || sample.starts_with('[')) && is_json(sample, truncated) {
+        return Some("json".to_string());
+    }
+
+    HINTS
+        .iter()
+        .map(|(lang, hints)| {
+            let hits = hints.iter().filter(|hint| sample.contains(*hint)).count();
+            (hits, *lang)
+        })
+        .filter(|(hits, _)| *hits >= 2)
+        // Earlier entries win ties, so they go in reversed for `max`.
+        .rev()
+        .max_by_key(|(hits, _)| *hits)
+        .map(|(_, lang)| lang.to_string())
+}
+
+/// Whether `sample` is a JSON document, or the start of one when it was
+/// cut off at [`SAMPLE_SIZE`].
+fn is_json(sample: &str, truncated: bool) -> bool {
+    match serde_json::from_str::<serde::de::IgnoredAny>(sample) {
+        Ok(_) => true,
+        Err(err) => truncated && err.is_eof(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filenames_take_precedence() {
+        assert_eq!(detect("#!/bin/sh", Some("build.rs")).as_deref(), Some("rs"));
+        assert_eq!(detect("", Some("Makefile")).as_deref(), Some("Makefile"));
+        assert_eq!(detect("", Some("notes.unknownext")), None);
+    }
+
+    #[test]
+    fn shebangs_and_first_lines_are_recognised() {
+        assert_eq!(
+            detect("#!/usr/bin/env python3\nprint(1)\n", None).as_deref(),
+            Some("py")
+        );
+        assert_eq!(
+            detect("#!/bin/bash\necho hi\n", None).as_deref(),
+            Some("sh")
+        );
+    }
+
+    #[test]
+    fn content_heuristics_cover_common_languages() {
+        assert_eq!(
+            detect("use std::io;\n\nfn main() {\n    let mut x = 1;\n}\n", None).as_deref(),
+            Some("rs")
+        );
+        assert_eq!(
+            detect("package main\n\nfunc main() {\n\tx := 1\n}\n", None).as_deref(),
+            Some("go")
+        );
+        assert_eq!(detect("{\"a\": [1, 2]}", None).as_deref(), Some("json"));
+        assert_eq!(detect("just some words", None), None);
+    }
+
+    #[test]
+    fn large_json_is_judged_by_its_sample() {
+        let items = vec!["\"item\""; SAMPLE_SIZE / 4].join(", ");
+        let json = format!("[{items}]");
+        assert!(json.len() > SAMPLE_SIZE);
+        assert_eq!(detect(&json, None).as_deref(), Some("json"));
+
+        // Broken within the sample, so not JSON however long it is.
+        let broken = format!("[1, 2,, {items}]");
+        assert_eq!(detect(&broken, None), None);
+    }
+}
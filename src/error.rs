@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::{expiry::format_timestamp, types};
+
+/// Everything a request can fail with. Each variant has a stable `code`
+/// for clients to match on, and the status it is answered with.
+#[derive(Debug)]
+pub enum PasteError {
+    /// A parameter or field that doesn't make sense.
+    BadRequest(String),
+    /// A JSON paste with one or more invalid fields.
+    InvalidPaste(Vec<types::FieldError>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound,
+    /// None of the types in `Accept` can be served.
+    NotAcceptable,
+    Conflict(String),
+    /// A paste with a view budget was read with a GET, which only shows
+    /// the confirmation page. Reading it takes a POST to `path`.
+    ConfirmationRequired {
+        path: String,
+    },
+    /// The paste existed but expired, or burned, at this Unix timestamp.
+    Gone {
+        expired_at: u64,
+        reason: types::GoneReason,
+    },
+    /// A request body or paste larger than `limit` bytes.
+    PayloadTooLarge {
+        what: &'static str,
+        limit: usize,
+    },
+    UnsupportedMediaType(String),
+    /// The store failed or couldn't be reached, worth retrying.
+    Storage(String),
+    /// A bug or an unexpected state on our side.
+    Internal(String),
+}
+
+impl PasteError {
+    pub fn status(&self) -> u16 {
+        match self {
+            PasteError::BadRequest(_) | PasteError::InvalidPaste(_) => 400,
+            PasteError::Unauthorized(_) => 401,
+            PasteError::Forbidden(_) => 403,
+            PasteError::NotFound => 404,
+            PasteError::NotAcceptable => 406,
+            PasteError::Conflict(_) | PasteError::ConfirmationRequired { .. } => 409,
+            PasteError::Gone { .. } => 410,
+            PasteError::PayloadTooLarge { .. } => 413,
+            PasteError::UnsupportedMediaType(_) => 415,
+            PasteError::Internal(_) => 500,
+            PasteError::Storage(_) => 503,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasteError::BadRequest(_) => "bad_request",
+            PasteError::InvalidPaste(_) => "invalid_paste",
+            PasteError::Unauthorized(_) => "unauthorized",
+            PasteError::Forbidden(_) => "forbidden",
+            PasteError::NotFound => "not_found",
+            PasteError::NotAcceptable => "not_acceptable",
+            PasteError::Conflict(_) => "conflict",
+            PasteError::ConfirmationRequired { .. } => "confirmation_required",
+            PasteError::Gone { .. } => "gone",
+            PasteError::PayloadTooLarge { .. } => "payload_too_large",
+            PasteError::UnsupportedMediaType(_) => "unsupported_media_type",
+            PasteError::Internal(_) => "internal",
+            PasteError::Storage(_) => "storage_unavailable",
+        }
+    }
+
+    pub fn to_response(&self) -> types::ErrorResponse {
+        let errors = match self {
+            PasteError::InvalidPaste(errors) => errors.clone(),
+            _ => Vec::new(),
+        };
+
+        types::ErrorResponse {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            errors,
+        }
+    }
+}
+
+impl fmt::Display for PasteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasteError::BadRequest(message)
+            | PasteError::Unauthorized(message)
+            | PasteError::Forbidden(message)
+            | PasteError::Conflict(message)
+            | PasteError::UnsupportedMediaType(message)
+            | PasteError::Storage(message)
+            | PasteError::Internal(message) => f.write_str(message),
+            PasteError::InvalidPaste(_) => f.write_str("invalid paste"),
+            PasteError::NotFound => f.write_str("paste not found"),
+            PasteError::NotAcceptable => f.write_str("can't serve any of the accepted types"),
+            PasteError::ConfirmationRequired { path } => write!(
+                f,
+                "this paste has a view limit, POST to {path} to read it and spend a view"
+            ),
+            PasteError::Gone {
+                expired_at,
+                reason: types::GoneReason::Expired,
+            } => write!(f, "paste expired at {}", format_timestamp(*expired_at)),
+            PasteError::Gone {
+                reason: types::GoneReason::Burned,
+                ..
+            } => f.write_str("this paste was burned after being read"),
+            PasteError::PayloadTooLarge { what, limit } => {
+                write!(f, "{what} exceeds {limit} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PasteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_and_codes_are_stable() {
+        let cases = [
+            (PasteError::NotFound, 404, "not_found"),
+            (
+                PasteError::ConfirmationRequired {
+                    path: "/abc".to_string(),
+                },
+                409,
+                "confirmation_required",
+            ),
+            (
+                PasteError::Gone {
+                    expired_at: 0,
+                    reason: types::GoneReason::Burned,
+                },
+                410,
+                "gone",
+            ),
+            (
+                PasteError::PayloadTooLarge {
+                    what: "paste",
+                    limit: 10,
+                },
+                413,
+                "payload_too_large",
+            ),
+            (PasteError::Internal("oops".to_string()), 500, "internal"),
+            (
+                PasteError::Storage("kv is down".to_string()),
+                503,
+                "storage_unavailable",
+            ),
+        ];
+
+        for (error, status, code) in cases {
+            assert_eq!(error.status(), status);
+            assert_eq!(error.code(), code);
+        }
+    }
+
+    #[test]
+    fn field_errors_are_listed_in_the_response() {
+        let response = PasteError::InvalidPaste(vec![types::FieldError {
+            field: "expiry".to_string(),
+            message: "invalid expiry: soon".to_string(),
+        }])
+        .to_response();
+
+        assert_eq!(response.code, "invalid_paste");
+        assert_eq!(response.errors[0].field, "expiry");
+        assert_eq!(
+            PasteError::PayloadTooLarge {
+                what: "paste",
+                limit: 10
+            }
+            .to_string(),
+            "paste exceeds 10 bytes"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{bail, Result};
+
+/// Workers KV rejects expiration TTLs shorter than a minute.
+pub const MIN_TTL: u64 = 60;
+
+/// How long a tombstone outlives the paste it stands for, so that late
+/// visitors get a 410 instead of a 404.
+pub const TOMBSTONE_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+/// Longest duration [`parse_duration`] accepts, about 100 years. Anything
+/// longer is as good as `never`, and keeps `now + ttl` far from overflowing.
+pub const MAX_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+pub fn tombstone_key(id: &str) -> String {
+    format!("tombstone:{id}")
+}
+
+/// Parses a duration such as `10m`, `1h`, `1d`, `2w` into seconds.
+/// `never` (or an empty value) yields `None`. Durations past
+/// [`MAX_DURATION`] are rejected.
+pub fn parse_duration(value: &str) -> Result<Option<u64>> {
+    let value = value.trim().to_ascii_lowercase();
+
+    if value.is_empty() || value == "never" {
+        return Ok(None);
+    }
+
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: u64 = match amount.parse() {
+        Ok(amount) if amount > 0 => amount,
+        _ => bail!("invalid expiry: {}", value),
+    };
+
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!("invalid expiry unit: {}", unit),
+    };
+
+    match amount.checked_mul(multiplier) {
+        Some(seconds) if seconds <= MAX_DURATION => Ok(Some(seconds)),
+        _ => bail!("expiry {} is longer than 100 years, use never", value),
+    }
+}
+
+/// Turns the requested expiry into the TTL that is actually applied,
+/// capping it at the server-side maximum. A paste that asks for `never`
+/// still expires after `max_ttl` when one is configured.
+pub fn resolve_ttl(requested: Option<&str>, max_ttl: Option<u64>) -> Result<Option<u64>> {
+    let requested = match requested {
+        Some(value) => parse_duration(value)?,
+        None => None,
+    };
+
+    let ttl = match (requested, max_ttl) {
+        (Some(ttl), Some(max)) => Some(ttl.min(max)),
+        (None, Some(max)) => Some(max),
+        (ttl, None) => ttl,
+    };
+
+    Ok(ttl.map(|ttl| ttl.max(MIN_TTL)))
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed_in_seconds() {
+        assert_eq!(parse_duration("90").unwrap(), Some(90));
+        assert_eq!(parse_duration("10m").unwrap(), Some(600));
+        assert_eq!(parse_duration(" 1H ").unwrap(), Some(3600));
+        assert_eq!(parse_duration("1d").unwrap(), Some(86400));
+        assert_eq!(parse_duration("2w").unwrap(), Some(1_209_600));
+        assert_eq!(parse_duration("never").unwrap(), None);
+        assert_eq!(parse_duration("").unwrap(), None);
+
+        for invalid in ["0", "-1d", "d", "1y", "soon", "1.5h"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn durations_are_bounded() {
+        assert_eq!(parse_duration("5200w").unwrap(), Some(5200 * 604_800));
+        assert!(parse_duration("5300w").is_err());
+        assert!(parse_duration(&format!("{}s", MAX_DURATION + 1)).is_err());
+        assert!(parse_duration(&format!("{}s", u64::MAX)).is_err());
+        assert!(parse_duration(&format!("{}w", u64::MAX / 2)).is_err());
+    }
+
+    #[test]
+    fn ttls_are_capped_and_floored() {
+        assert_eq!(resolve_ttl(None, None).unwrap(), None);
+        assert_eq!(resolve_ttl(Some("never"), None).unwrap(), None);
+        assert_eq!(resolve_ttl(Some("1d"), None).unwrap(), Some(86400));
+
+        // The server-side maximum applies to `never` too.
+        assert_eq!(resolve_ttl(Some("2w"), Some(86400)).unwrap(), Some(86400));
+        assert_eq!(
+            resolve_ttl(Some("never"), Some(86400)).unwrap(),
+            Some(86400)
+        );
+        assert_eq!(resolve_ttl(None, Some(86400)).unwrap(), Some(86400));
+
+        // KV won't take TTLs under a minute.
+        assert_eq!(resolve_ttl(Some("5s"), None).unwrap(), Some(MIN_TTL));
+        assert!(resolve_ttl(Some("soon"), Some(86400)).is_err());
+    }
+
+    #[test]
+    fn timestamps_are_formatted_as_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+        assert_eq!(format_timestamp(1_709_251_199), "2024-02-29 23:59 UTC");
+        assert_eq!(format_timestamp(4_102_444_800), "2100-01-01 00:00 UTC");
+    }
+}
@@ -1,181 +1,2439 @@
-use crate::{types, utils::syntax_highlight_code};
-use rand::{distributions::Alphanumeric, Rng};
-use std::result::Result;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
 
-use worker::*;
+use futures_util::FutureExt;
 
-pub async fn create_paste(mut req: Request, ctx: RouteContext<()>) -> Result<Response, Error> {
-    let form = req.form_data().await?;
+use crate::{
+    chunks,
+    config::Config,
+    detect,
+    error::PasteError,
+    expiry,
+    highlight::{self, Colors, LineRanges, ThemeChoice},
+    id::{self, IdGenerator},
+    paste_path::PastePath,
+    slug,
+    store::{PasteStore, PutOptions},
+    templates::Templates,
+    token, types,
+    utils::{
+        get_confirm_template, get_error_template, get_web_template, raw_content_type,
+        render_markdown, syntax_highlight_ansi, syntax_highlight_code,
+    },
+    web::{FormValue, Incoming, Reply},
+};
 
-    let code_paste_kv = ctx.kv("code_paste")?;
+/// Everything a handler needs besides the request itself.
+pub struct App<'a> {
+    pub store: &'a dyn PasteStore,
+    pub config: &'a Config,
+    /// Unix timestamp of the request, in seconds.
+    pub now: u64,
+}
+
+impl App<'_> {
+    /// Origin permalinks are built on: the configured base URL, else the
+    /// one the request came in on.
+    fn base_url(&self, req: &Incoming) -> String {
+        match &self.config.base_url {
+            Some(base_url) => base_url.clone(),
+            None => req.origin.clone(),
+        }
+    }
+
+    fn templates(&self) -> Rc<Templates<'static>> {
+        Templates::cached(&self.config.templates)
+    }
+
+    /// An error as a styled HTML page or as JSON, whichever the client
+    /// asked for.
+    pub fn fail(&self, req: &Incoming, error: PasteError) -> Reply {
+        let status = error.status();
+        let json = Reply::json(status, &error.to_response());
+
+        if !req.wants_html() {
+            return json;
+        }
+
+        match get_error_template(&self.templates(), status, error.code(), &error.to_string()) {
+            Ok(page) => Reply::html(status, page),
+            Err(err) => {
+                log_error!("error rendering error page: {:?}", err);
+                json
+            }
+        }
+    }
+}
+
+/// Runs a handler, answering with a 500 instead of dropping the connection
+/// when it panics. `req` picks between the JSON and HTML error, so a copy
+/// of the headers is enough when the handler takes the request. Workers
+/// builds abort on panic instead of unwinding: there the panic hook logs
+/// the message and the runtime answers the 500.
+pub async fn guarded<F>(app: &App<'_>, req: &Incoming, handler: F) -> Reply
+where
+    F: Future<Output = Reply>,
+{
+    match AssertUnwindSafe(handler).catch_unwind().await {
+        Ok(reply) => reply,
+        Err(_) => app.fail(
+            req,
+            PasteError::Internal("the server hit an unexpected error".to_string()),
+        ),
+    }
+}
+
+pub async fn index(app: &App<'_>, req: &Incoming) -> Reply {
+    match get_web_template(&app.templates(), app.config.max_paste_size) {
+        Ok(page) => Reply::html(200, page).with_header("Cache-Control", "max-age=2629746"),
+        Err(err) => {
+            log_error!("error rendering index: {:?}", err);
+            app.fail(
+                req,
+                PasteError::Internal("couldn't render page".to_string()),
+            )
+        }
+    }
+}
+
+/// Limits a client can check before uploading, served from `/api`.
+pub async fn discovery(app: &App<'_>, _req: &Incoming) -> Reply {
+    Reply::json(
+        200,
+        &types::Limits {
+            max_paste_size: app.config.max_paste_size,
+            max_request_size: app.config.max_request_size,
+            chunk_size: app.config.chunk_size,
+            max_expiry: app.config.max_expiry,
+        },
+    )
+}
+
+/// A paste to store, as read from the form or from JSON.
+struct NewPaste {
+    content: String,
+    language: Option<String>,
+    filename: Option<String>,
+    ttl: Option<u64>,
+    views_remaining: Option<u64>,
+    visibility: types::Visibility,
+    slug: Option<String>,
+}
+
+/// What [`store_paste`] wrote.
+struct StoredPaste {
+    id: String,
+    delete_token: String,
+    metadata: types::PasteMetadata,
+}
+
+impl StoredPaste {
+    /// The paste's path, with the language's extension so it opens
+    /// highlighted.
+    fn path(&self) -> String {
+        match &self.metadata.language {
+            Some(lang) => format!("/{}.{}", self.id, lang),
+            None => format!("/{}", self.id),
+        }
+    }
 
-    let id: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(7)
-        .map(char::from)
-        .collect();
+    fn response(self, base_url: &str) -> types::CodePaseResponse {
+        let metadata = self.metadata;
 
-    let code = match form.get("content") {
-        Some(FormEntry::File(file)) => {
-            let bytes = file.bytes().await?;
+        types::CodePaseResponse {
+            permalink: format!("{}/{}", base_url, self.id),
+            id: self.id,
+            delete_token: self.delete_token,
+            language: metadata.language,
+            filename: metadata.filename,
+            created_at: metadata.created_at,
+            size: metadata.size,
+            expires_at: metadata.expires_at,
+            views_remaining: metadata.views_remaining,
+            visibility: metadata.visibility,
+        }
+    }
+}
+
+pub async fn create_paste(app: &App<'_>, mut req: Incoming) -> Reply {
+    if req.body_too_large {
+        return app.fail(&req, request_too_large(app));
+    }
+
+    if req.is_json() {
+        return create_paste_json(app, req).await;
+    }
+
+    let mut filename = None;
+    let code = match req.form.remove("content") {
+        Some(FormValue::File { name, bytes }) => {
+            filename = Some(name).filter(|name| !name.is_empty());
 
             match String::from_utf8(bytes) {
                 Ok(v) => v,
                 Err(e) => {
-                    console_error!("Invalid UTF-8 sequence: {}", e);
-
-                    return Response::from_json(&types::JsonResponse {
-                        message: "couldn't parse file".to_string(),
-                    })
-                    .map(|res| res.with_status(400));
+                    log_error!("Invalid UTF-8 sequence: {}", e);
+                    return app.fail(
+                        &req,
+                        PasteError::BadRequest("couldn't parse file".to_string()),
+                    );
                 }
             }
         }
-        Some(FormEntry::Field(c)) => c,
+        Some(FormValue::Field(c)) => c,
+        // `curl --data-binary @file` labels the file as a urlencoded form,
+        // so any body that isn't a form with `content` is the paste itself.
+        None if !req.body.is_empty()
+            && req.content_type().as_deref() != Some("multipart/form-data") =>
+        {
+            return upload_paste(app, req, None).await
+        }
         None => {
-            return Response::from_json(&types::JsonResponse {
-                message: "missing 'content' field".to_string(),
-            })
-            .map(|res| res.with_status(400))
+            return app.fail(
+                &req,
+                PasteError::BadRequest("missing 'content' field".to_string()),
+            )
         }
     };
 
-    match code_paste_kv.put(id.as_str(), code)?.execute().await {
-        Ok(..) => {
-            let _mime_json = "application/json".to_string();
-            let accept = match req.headers().get("accept") {
-                Ok(Some(value)) => value,
-                _ => "".to_string(),
-            };
+    let new = match new_paste(app, code, filename, |name| req.field(name)) {
+        Ok(new) => new,
+        Err(err) => return app.fail(&req, err),
+    };
 
-            if accept == _mime_json {
-                return Response::from_json(&types::CodePaseResponse {
-                    permalink: format!("http://paste.priver.dev/{}", id.as_str()),
-                    id: id.to_string(),
-                });
-            }
+    let stored = match store_paste(app, &req, new).await {
+        Ok(stored) => stored,
+        Err(err) => return app.fail(&req, err),
+    };
 
-            let language = match form.get("language") {
-                Some(FormEntry::Field(c)) => format!(".{}", c),
-                __ => "".to_string(),
-            };
+    // Form clients asking for JSON have always been answered with a 200,
+    // unlike the JSON API's 201.
+    if req.negotiate(&["text/html", "application/json"]) == Some("application/json") {
+        return Reply::json(200, &stored.response(&app.base_url(&req)));
+    }
+
+    // One cookie holds the tokens of the browser's latest pastes, rather
+    // than one each, so the Cookie header sent with every request stays
+    // bounded.
+    let tokens = token::add_to_cookie(req.cookie(token::COOKIE), &stored.id, &stored.delete_token);
+
+    Reply::redirect(&stored.path()).with_header(
+        "Set-Cookie",
+        &format!(
+            "{}={}; Path=/; Max-Age={}; Secure; HttpOnly; SameSite=Strict",
+            token::COOKIE,
+            tokens,
+            DELETE_COOKIE_MAX_AGE
+        ),
+    )
+}
+
+/// How long a browser keeps the delete tokens of its pastes, in seconds.
+const DELETE_COOKIE_MAX_AGE: u64 = 30 * 24 * 60 * 60;
+
+/// `PUT /:filename`, e.g. `curl -T notes.md`. The body is the paste and
+/// the filename picks its language.
+pub async fn put_paste(app: &App<'_>, req: Incoming) -> Reply {
+    if req.body_too_large {
+        return app.fail(&req, request_too_large(app));
+    }
 
-            Response::redirect(
-                format!("http://paste.priver.dev/{}{}", id.as_str(), language)
-                    .parse()
-                    .unwrap(),
+    let filename = req.param("filename").map(str::to_string);
+
+    upload_paste(app, req, filename).await
+}
+
+/// Stores the raw request body as a paste, taking options such as
+/// `?expiry=` from the query string. Answers 201 with the permalink as
+/// plain text, the delete token goes in `X-Delete-Token`.
+async fn upload_paste(app: &App<'_>, mut req: Incoming, filename: Option<String>) -> Reply {
+    let code = match String::from_utf8(std::mem::take(&mut req.body)) {
+        Ok(code) => code,
+        Err(_) => {
+            return app.fail(
+                &req,
+                PasteError::BadRequest("paste must be UTF-8 text".to_string()),
             )
         }
-        Err(err) => {
-            console_error!("error posting data to KV: {:?}", err.to_string());
-            Response::from_json(&types::JsonResponse {
-                message: "couldn't add pase to database".to_string(),
-            })
-            .map(|res| res.with_status(400))
+    };
+
+    let new = match new_paste(app, code, filename, |name| req.query(name)) {
+        Ok(new) => new,
+        Err(err) => return app.fail(&req, err),
+    };
+
+    let stored = match store_paste(app, &req, new).await {
+        Ok(stored) => stored,
+        Err(err) => return app.fail(&req, err),
+    };
+
+    let link = format!("{}{}", app.base_url(&req), stored.path());
+
+    Reply::new(201, "text/plain; charset=utf-8", format!("{link}\n"))
+        .with_header("Location", &link)
+        .with_header("X-Delete-Token", &stored.delete_token)
+}
+
+/// Reads the options of a form or raw upload, looked up with `field`.
+fn new_paste<'r, F>(
+    app: &App<'_>,
+    content: String,
+    filename: Option<String>,
+    field: F,
+) -> Result<NewPaste, PasteError>
+where
+    F: Fn(&str) -> Option<&'r str>,
+{
+    if content.len() > app.config.max_paste_size {
+        return Err(paste_too_large(app));
+    }
+
+    let ttl = expiry::resolve_ttl(field("expiry"), app.config.max_expiry)
+        .map_err(|err| PasteError::BadRequest(err.to_string()))?;
+
+    let views_remaining = match (field("burn_after_read"), field("max_views")) {
+        (Some("on" | "true"), _) => Some(1),
+        (_, Some(value)) if !value.is_empty() => match value.parse::<u64>() {
+            Ok(views) if views > 0 => Some(views),
+            _ => {
+                return Err(PasteError::BadRequest(
+                    "'max_views' must be a positive number".to_string(),
+                ))
+            }
+        },
+        _ => None,
+    };
+
+    let visibility = match field("visibility").filter(|value| !value.is_empty()) {
+        Some(value) => value
+            .parse::<types::Visibility>()
+            .map_err(|err| PasteError::BadRequest(err.to_string()))?,
+        None => types::Visibility::default(),
+    };
+
+    let language = match field("language").filter(|value| !value.is_empty()) {
+        Some(value) => Some(highlight::resolve_language_override(value).ok_or_else(|| {
+            PasteError::BadRequest(format!("unknown language '{value}', see /api/languages"))
+        })?),
+        None => None,
+    };
+
+    Ok(NewPaste {
+        content,
+        language,
+        filename,
+        ttl,
+        views_remaining,
+        visibility,
+        slug: field("slug")
+            .filter(|slug| !slug.is_empty())
+            .map(str::to_string),
+    })
+}
+
+/// `POST /api/pastes`, and `POST /` with a JSON body. Answers 201 with the
+/// stored paste, or 400 listing every field that didn't validate.
+pub async fn create_paste_json(app: &App<'_>, mut req: Incoming) -> Reply {
+    if req.body_too_large {
+        return app.fail(&req, request_too_large(app));
+    }
+
+    if !req.is_json() {
+        return app.fail(
+            &req,
+            PasteError::UnsupportedMediaType("expected Content-Type: application/json".to_string()),
+        );
+    }
+
+    // The body isn't needed once parsed, so it's dropped right away rather
+    // than held next to the paste until the handler returns.
+    let input: types::CodePasteInput = match serde_json::from_slice(&std::mem::take(&mut req.body))
+    {
+        Ok(input) => input,
+        Err(err) => return app.fail(&req, PasteError::BadRequest(format!("invalid JSON: {err}"))),
+    };
+
+    if let Some(content) = &input.content {
+        if content.len() > app.config.max_paste_size {
+            return app.fail(&req, paste_too_large(app));
+        }
+    }
+
+    let new = match validate_input(app, input) {
+        Ok(new) => new,
+        Err(errors) => return app.fail(&req, PasteError::InvalidPaste(errors)),
+    };
+
+    match store_paste(app, &req, new).await {
+        Ok(stored) => Reply::json(201, &stored.response(&app.base_url(&req))),
+        Err(err) => app.fail(&req, err),
+    }
+}
+
+fn request_too_large(app: &App<'_>) -> PasteError {
+    PasteError::PayloadTooLarge {
+        what: "request",
+        limit: app.config.max_request_size,
+    }
+}
+
+fn paste_too_large(app: &App<'_>) -> PasteError {
+    PasteError::PayloadTooLarge {
+        what: "paste",
+        limit: app.config.max_paste_size,
+    }
+}
+
+/// Checks every field of a JSON paste, collecting all problems rather than
+/// stopping at the first.
+fn validate_input(
+    app: &App<'_>,
+    input: types::CodePasteInput,
+) -> Result<NewPaste, Vec<types::FieldError>> {
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message: String| {
+        errors.push(types::FieldError {
+            field: field.to_string(),
+            message,
+        })
+    };
+
+    if input.content.is_none() {
+        error("content", "is required".to_string());
+    }
+
+    let language = match input.language.filter(|value| !value.is_empty()) {
+        Some(value) => match highlight::resolve_language_override(&value) {
+            Some(lang) => Some(lang),
+            None => {
+                error(
+                    "language",
+                    format!("unknown language '{value}', see /api/languages"),
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let filename = input.filename.filter(|name| !name.is_empty());
+    if let Some(name) = &filename {
+        if name.len() > 255 {
+            error("filename", "must be at most 255 bytes".to_string());
+        } else if name.contains(['/', '\\']) {
+            error("filename", "must not contain path separators".to_string());
+        }
+    }
+
+    let ttl =
+        expiry::resolve_ttl(input.expiry.as_deref(), app.config.max_expiry).unwrap_or_else(|err| {
+            error("expiry", err.to_string());
+            None
+        });
+
+    let visibility = match input.visibility.filter(|value| !value.is_empty()) {
+        Some(value) => value.parse().unwrap_or_else(|err: anyhow::Error| {
+            error("visibility", err.to_string());
+            types::Visibility::default()
+        }),
+        None => types::Visibility::default(),
+    };
+
+    let views_remaining = match (input.burn_after_read, input.max_views) {
+        (true, Some(_)) => {
+            error(
+                "max_views",
+                "can't be combined with burn_after_read".to_string(),
+            );
+            None
+        }
+        (true, None) => Some(1),
+        (false, Some(0)) => {
+            error("max_views", "must be a positive number".to_string());
+            None
         }
+        (false, views) => views,
+    };
+
+    let slug = input.slug.filter(|slug| !slug.is_empty());
+    if let Some(Err(err)) = slug.as_deref().map(slug::validate) {
+        error("slug", err.to_string());
+    }
+
+    match (input.content, errors.is_empty()) {
+        (Some(content), true) => Ok(NewPaste {
+            content,
+            language,
+            filename,
+            ttl,
+            views_remaining,
+            visibility,
+            slug,
+        }),
+        _ => Err(errors),
     }
 }
 
-pub async fn get_paste(ctx: RouteContext<()>) -> Result<Response, Error> {
-    let param: Vec<&str> = match ctx.param("id") {
-        Some(value) => value.split(".").collect(),
+/// Picks the id, claiming the slug if one was asked for, and writes the
+/// paste with its chunks, tombstone and slug reservation.
+async fn store_paste(
+    app: &App<'_>,
+    req: &Incoming,
+    new: NewPaste,
+) -> Result<StoredPaste, PasteError> {
+    let NewPaste {
+        content,
+        language,
+        filename,
+        ttl,
+        views_remaining,
+        visibility,
+        slug,
+    } = new;
+
+    let store = app.store;
+    let mut owner_token = None;
+
+    let id = match slug {
+        Some(slug) => {
+            owner_token = Some(claim_slug(app, req, &slug).await?);
+            slug
+        }
         None => {
-            return Response::from_json(&types::JsonResponse {
-                message: "missing id".to_string(),
-            })
-            .map(|res| res.with_status(404));
+            let generated = IdGenerator::from_config(app.config)
+                .generate(|candidate| async move {
+                    // Generated ids mustn't land on a slug reserved for later.
+                    Ok(store.exists(&candidate).await?
+                        || store.exists(&slug::reservation_key(&candidate)).await?)
+                })
+                .await;
+
+            match generated {
+                Ok(value) => value,
+                Err(err) => {
+                    log_error!("error generating paste id: {:?}", err.to_string());
+                    return Err(PasteError::Storage(
+                        "couldn't allocate a paste id".to_string(),
+                    ));
+                }
+            }
         }
     };
 
-    let id = param[0];
-    console_log!("{}", id);
+    let expires_at = ttl.map(|ttl| app.now.saturating_add(ttl));
+
+    // KV metadata is capped at 1024 bytes, so overly long names are dropped.
+    let filename = filename.filter(|name| name.len() <= 255);
+
+    let language = language.or_else(|| detect::detect(&content, filename.as_deref()));
 
-    let code_paste_kv = match ctx.kv("code_paste") {
+    let mut record = types::PasteRecord::new(content, language.clone(), filename.clone(), app.now);
+
+    if record.size > app.config.chunk_size {
+        // Chunks go in before the record, so a record never points at
+        // chunks that don't exist yet.
+        let written = chunks::write(
+            app.store,
+            &id,
+            &record.content,
+            app.config.chunk_size,
+            expires_at,
+        )
+        .await;
+
+        match written {
+            Ok(count) => {
+                record.chunks = Some(count);
+                record.content = String::new();
+            }
+            Err(err) => {
+                log_error!("error writing paste chunks: {:?}", err.to_string());
+                return Err(PasteError::Storage("couldn't store paste".to_string()));
+            }
+        }
+    }
+
+    let slugged = owner_token.is_some();
+    let delete_token = owner_token.unwrap_or_else(token::generate);
+    let metadata = types::PasteMetadata {
+        version: record.version,
+        delete_token_hash: token::hash(&delete_token),
+        expires_at,
+        views_remaining,
+        language,
+        filename,
+        created_at: record.created_at,
+        size: record.size,
+        visibility,
+    };
+
+    let value = match serde_json::to_string(&record) {
         Ok(value) => value,
         Err(err) => {
-            console_error!("error reading kv: {:?}", err.to_string());
-            return Response::from_json(&types::JsonResponse {
-                message: "missing id".to_string(),
-            })
-            .map(|res| res.with_status(404));
-        }
-    };
-
-    match code_paste_kv.get(id).text().await {
-        Ok(Some(value)) => {
-            let mut headers: http::HeaderMap = Headers::new().into();
-            headers.append("Cache-Control", "max-age=2629746".parse().unwrap());
-            headers.append("Content-Type", "text/html".parse().unwrap());
-
-            match param.len() > 1 {
-                true => {
-                    let ext = param[1];
-
-                    let rendered = match syntax_highlight_code(value, ext.to_string()) {
-                        Ok(value) => value,
-                        Err(error) => {
-                            console_log!("{:?}", error);
-                            return Response::from_json(&types::JsonResponse {
-                                message: "couldn't syntax highlight code".to_string(),
-                            })
-                            .map(|res| res.with_status(500));
-                        }
-                    };
-
-                    return Response::ok(rendered).map(|res| res.with_headers(headers.into()));
-                }
-                false => {
-                    return Response::ok(value).map(|res| res.with_headers(headers.into()));
-                }
-            }
+            log_error!("error serializing paste: {:?}", err.to_string());
+            return Err(PasteError::Internal("couldn't store paste".to_string()));
         }
-        Ok(None) => Response::from_json(&types::JsonResponse {
-            message: "missing id".to_string(),
-        })
-        .map(|res| res.with_status(404)),
+    };
+
+    let put = app
+        .store
+        .put(
+            &id,
+            value,
+            PutOptions {
+                metadata: Some(metadata.clone()),
+                expires_at,
+            },
+        )
+        .await;
+
+    if let Err(err) = put {
+        log_error!("error posting data to KV: {:?}", err.to_string());
+        return Err(PasteError::Storage("couldn't store paste".to_string()));
+    }
+
+    if let Some(expired_at) = expires_at {
+        write_tombstone(app.store, &id, expired_at, types::GoneReason::Expired).await;
+    }
+
+    if slugged {
+        // Publishing again renews the reservation.
+        let reserved_until = expires_at.unwrap_or(app.now);
+        if let Err(err) = write_reservation(app, &id, &delete_token, reserved_until).await {
+            log_error!("error reserving slug: {:?}", err.to_string());
+        }
+    }
+
+    Ok(StoredPaste {
+        id,
+        delete_token,
+        metadata,
+    })
+}
+
+async fn slug_reservation(
+    app: &App<'_>,
+    slug: &str,
+) -> anyhow::Result<Option<types::SlugReservation>> {
+    match app.store.get(&slug::reservation_key(slug)).await? {
+        Some(stored) => Ok(Some(serde_json::from_str(&stored.value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reserves `slug` for the holder of `owner_token`, until
+/// [`Config::slug_reservation_ttl`] after `from`.
+async fn write_reservation(
+    app: &App<'_>,
+    slug: &str,
+    owner_token: &str,
+    from: u64,
+) -> anyhow::Result<()> {
+    let reservation = types::SlugReservation {
+        owner_token_hash: token::hash(owner_token),
+        reserved_at: app.now,
+    };
+
+    app.store
+        .put(
+            &slug::reservation_key(slug),
+            serde_json::to_string(&reservation)?,
+            PutOptions {
+                metadata: None,
+                expires_at: app
+                    .config
+                    .slug_reservation_ttl
+                    .map(|ttl| from.saturating_add(ttl)),
+            },
+        )
+        .await
+}
+
+/// Whether `token` is one of the operator's [`Config::owner_tokens`].
+fn is_owner_token(app: &App<'_>, token: &str) -> bool {
+    app.config
+        .owner_tokens
+        .iter()
+        .any(|owner| token::verify(token, &token::hash(owner)))
+}
+
+/// Checks that `slug` is valid and free for this request, and returns the
+/// token that owns it: the one the slug is reserved for, or a new one. A
+/// slug reserved by someone else, or held by a live paste, is a conflict.
+async fn claim_slug(app: &App<'_>, req: &Incoming, slug: &str) -> Result<String, PasteError> {
+    slug::validate(slug).map_err(|err| PasteError::BadRequest(err.to_string()))?;
+
+    let taken = match app.store.exists(slug).await {
+        Ok(taken) => taken,
+        Err(err) => {
+            log_error!("error reading kv data: {:?}", err.to_string());
+            return Err(PasteError::Storage("couldn't check slug".to_string()));
+        }
+    };
+
+    if taken {
+        return Err(PasteError::Conflict("slug is taken".to_string()));
+    }
+
+    match slug_reservation(app, slug).await {
+        Ok(Some(reservation)) => match presented_delete_token(req) {
+            Some(token) if token::verify(token, &reservation.owner_token_hash) => {
+                Ok(token.to_string())
+            }
+            _ => Err(PasteError::Conflict("slug is reserved".to_string())),
+        },
+        Ok(None) => Ok(token::generate()),
         Err(err) => {
-            console_error!("error reading kv data: {:?}", err.to_string());
-            Response::from_json(&types::JsonResponse {
-                message: "missing id".to_string(),
-            })
-            .map(|res| res.with_status(404))
+            log_error!("error reading slug reservation: {:?}", err.to_string());
+            Err(PasteError::Storage("couldn't check slug".to_string()))
         }
     }
 }
 
-pub async fn delete_paste(ctx: RouteContext<()>) -> Result<Response, Error> {
-    let id = match ctx.param("id") {
+/// Reserves a slug ahead of publishing a paste under it, for one of the
+/// operator's owner tokens. Reserving it again renews the reservation.
+pub async fn reserve_slug(app: &App<'_>, req: &Incoming) -> Reply {
+    let slug = match req.param("slug") {
         Some(value) => value,
-        None => {
-            return Response::from_json(&types::JsonResponse {
-                message: "missing id".to_string(),
-            })
-            .map(|res| res.with_status(404));
+        None => return app.fail(req, PasteError::NotFound),
+    };
+
+    if let Err(err) = slug::validate(slug) {
+        return app.fail(req, PasteError::BadRequest(err.to_string()));
+    }
+
+    let owner_token = match presented_delete_token(req) {
+        Some(token) if is_owner_token(app, token) => token,
+        _ => {
+            return app.fail(
+                req,
+                PasteError::Unauthorized("an owner token is required to reserve slugs".to_string()),
+            )
         }
     };
 
-    let code_paste_kv = match ctx.kv("code_paste") {
+    let unavailable = || {
+        app.fail(
+            req,
+            PasteError::Storage("couldn't reserve slug".to_string()),
+        )
+    };
+
+    let renewed = match slug_reservation(app, slug).await {
+        Ok(Some(reservation)) if token::verify(owner_token, &reservation.owner_token_hash) => true,
+        Ok(Some(_)) => return app.fail(req, PasteError::Conflict("slug is reserved".to_string())),
+        Ok(None) => false,
+        Err(err) => {
+            log_error!("error reading slug reservation: {:?}", err.to_string());
+            return unavailable();
+        }
+    };
+
+    match app.store.exists(slug).await {
+        Ok(taken) if !taken || renewed => {}
+        Ok(_) => return app.fail(req, PasteError::Conflict("slug is taken".to_string())),
+        Err(err) => {
+            log_error!("error reading kv data: {:?}", err.to_string());
+            return unavailable();
+        }
+    }
+
+    match write_reservation(app, slug, owner_token, app.now).await {
+        Ok(()) if renewed => Reply::message(200, "reserved"),
+        Ok(()) => Reply::message(201, "reserved"),
+        Err(err) => {
+            log_error!("error reserving slug: {:?}", err.to_string());
+            unavailable()
+        }
+    }
+}
+
+/// Records that `id` is gone as of `expired_at`, and why, kept around for
+/// [`expiry::TOMBSTONE_RETENTION`] after that.
+async fn write_tombstone(
+    store: &dyn PasteStore,
+    id: &str,
+    expired_at: u64,
+    reason: types::GoneReason,
+) {
+    let tombstone = match serde_json::to_string(&types::Tombstone { expired_at, reason }) {
         Ok(value) => value,
         Err(err) => {
-            console_error!("error reading kv: {:?}", err.to_string());
-            return Response::from_json(&types::JsonResponse {
-                message: "missing id".to_string(),
-            })
-            .map(|res| res.with_status(404));
+            log_error!("error serializing tombstone: {:?}", err.to_string());
+            return;
         }
     };
 
-    match code_paste_kv.delete(id).await {
-        Ok(..) => Response::from_json(&types::JsonResponse {
-            message: "deleted".to_string(),
-        })
-        .map(|res| res.with_status(200)),
+    let put = store
+        .put(
+            &expiry::tombstone_key(id),
+            tombstone,
+            PutOptions {
+                metadata: None,
+                expires_at: Some(expired_at.saturating_add(expiry::TOMBSTONE_RETENTION)),
+            },
+        )
+        .await;
+
+    if let Err(err) = put {
+        log_error!("error writing tombstone: {:?}", err.to_string());
+    }
+}
+
+pub async fn get_paste(app: &App<'_>, req: &Incoming) -> Reply {
+    serve_paste(app, req, false, false).await
+}
+
+/// POST counterpart of [`get_paste`], submitted from the confirmation page
+/// of pastes with a view budget. Link-preview bots only issue GETs, so they
+/// can't burn a paste.
+pub async fn reveal_paste(app: &App<'_>, req: &Incoming) -> Reply {
+    serve_paste(app, req, true, false).await
+}
+
+/// The paste content itself, typed after the extension when it's a safe
+/// one and as plain text otherwise.
+pub async fn raw_paste(app: &App<'_>, req: &Incoming) -> Reply {
+    serve_paste(app, req, false, true).await
+}
+
+pub async fn reveal_raw_paste(app: &App<'_>, req: &Incoming) -> Reply {
+    serve_paste(app, req, true, true).await
+}
+
+/// Spends one view of a paste's budget. Workers KV has no compare-and-swap,
+/// so two readers racing on the last view may both see the paste; the paste
+/// is deleted as soon as its budget reaches zero.
+async fn consume_view(
+    app: &App<'_>,
+    id: &str,
+    value: &str,
+    metadata: &types::PasteMetadata,
+    chunk_count: Option<u32>,
+    views_remaining: u64,
+) -> anyhow::Result<()> {
+    if views_remaining <= 1 {
+        app.store.delete(id).await?;
+        if let Some(count) = chunk_count {
+            chunks::delete(app.store, id, count).await?;
+        }
+        write_tombstone(app.store, id, app.now, types::GoneReason::Burned).await;
+
+        return Ok(());
+    }
+
+    app.store
+        .put(
+            id,
+            value.to_string(),
+            PutOptions {
+                metadata: Some(types::PasteMetadata {
+                    views_remaining: Some(views_remaining - 1),
+                    ..metadata.clone()
+                }),
+                expires_at: metadata.expires_at,
+            },
+        )
+        .await
+}
+
+/// How a paste is served, picked by the route and the `Accept` header.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    /// The rendered, highlighted page.
+    Page,
+    /// The content as plain text.
+    Text,
+    /// The content typed after its extension, from `/raw/:id`.
+    Raw,
+    /// The content along with its metadata.
+    Json,
+    /// The content highlighted with terminal escapes.
+    Ansi(Colors),
+}
+
+/// Picks the representation of `GET /:id`, from `?format=` or else the
+/// `Accept` header. Terminals get plain text unless they ask for something
+/// else, coloured when the request names a language.
+fn read_format(req: &Incoming, names_language: bool) -> Result<Format, PasteError> {
+    match req.query("format") {
+        Some("ansi") => return Ok(Format::Ansi(Colors::TrueColor)),
+        Some("ansi256") => return Ok(Format::Ansi(Colors::Palette256)),
+        Some("text") => return Ok(Format::Text),
+        Some(other) => return Err(PasteError::BadRequest(format!("unknown format '{other}'"))),
+        None => {}
+    }
+
+    let offered: &[&str] = match req.is_terminal() {
+        true => &["text/plain", "text/html", "application/json"],
+        false => &["text/html", "application/json", "text/plain"],
+    };
+
+    match req.negotiate(offered) {
+        Some("text/plain") if req.is_terminal() && names_language => {
+            Ok(Format::Ansi(Colors::TrueColor))
+        }
+        Some("text/plain") => Ok(Format::Text),
+        Some("application/json") => Ok(Format::Json),
+        Some(_) => Ok(Format::Page),
+        None => Err(PasteError::NotAcceptable),
+    }
+}
+
+async fn serve_paste(app: &App<'_>, req: &Incoming, reveal: bool, raw: bool) -> Reply {
+    let path = match req.param("id") {
+        Some(value) => PastePath::parse(value, req.param("filename")),
+        None => return app.fail(req, PasteError::NotFound),
+    };
+
+    // `?lang=` overrides whatever language the path names.
+    let requested_lang = match req.query("lang").filter(|value| !value.is_empty()) {
+        Some(value) => match highlight::resolve_language_override(value) {
+            Some(lang) => Some(lang),
+            None => {
+                return app.fail(
+                    req,
+                    PasteError::BadRequest(format!("unknown language '{value}'")),
+                )
+            }
+        },
+        None => path.language(),
+    };
+
+    let format = match raw {
+        true => Format::Raw,
+        false => match read_format(req, requested_lang.is_some()) {
+            Ok(format) => format,
+            Err(err) => return app.fail(req, err),
+        },
+    };
+
+    let id = path.id;
+    if !id::is_paste_id(id) {
+        return app.fail(req, PasteError::NotFound);
+    }
+
+    let stored = match app.store.get(id).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => {
+            let tombstone = app
+                .store
+                .get(&expiry::tombstone_key(id))
+                .await
+                .ok()
+                .flatten()
+                .and_then(|stored| serde_json::from_str::<types::Tombstone>(&stored.value).ok());
+
+            let error = match tombstone {
+                Some(tombstone) if tombstone.expired_at <= app.now => PasteError::Gone {
+                    expired_at: tombstone.expired_at,
+                    reason: tombstone.reason,
+                },
+                _ => PasteError::NotFound,
+            };
+
+            return app.fail(req, error);
+        }
+        Err(err) => {
+            log_error!("error reading kv data: {:?}", err.to_string());
+            return app.fail(req, PasteError::Storage("couldn't read paste".to_string()));
+        }
+    };
+
+    let metadata = stored.metadata;
+    let expires_at = metadata.as_ref().and_then(|metadata| metadata.expires_at);
+    let views_remaining = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.views_remaining);
+    let visibility = metadata
+        .as_ref()
+        .map(|metadata| metadata.visibility)
+        .unwrap_or_default();
+
+    if let (false, Some(views)) = (reveal, views_remaining) {
+        let path = match raw {
+            true => format!("raw/{}", path.to_path()),
+            false => path.to_path(),
+        };
+
+        // Only browsers get a page to confirm on, other clients are told
+        // how to read the paste.
+        if format != Format::Page && !req.wants_html() {
+            return app.fail(
+                req,
+                PasteError::ConfirmationRequired {
+                    path: format!("/{path}"),
+                },
+            );
+        }
+
+        return match get_confirm_template(&app.templates(), &path, views) {
+            Ok(page) => Reply::html(200, page).with_header("Cache-Control", "no-store"),
+            Err(err) => {
+                log_error!("error rendering confirmation: {:?}", err);
+                app.fail(
+                    req,
+                    PasteError::Internal("couldn't render page".to_string()),
+                )
+            }
+        };
+    }
+
+    let mut record = match types::PasteRecord::decode(&stored.value, metadata.as_ref()) {
+        Ok(record) => record,
         Err(err) => {
-            console_error!("error deleting from KV: {:?}", err.to_string());
-            Response::from_json(&types::JsonResponse {
-                message: "missing id".to_string(),
-            })
-            .map(|res| res.with_status(404))
+            log_error!("error decoding paste record: {:?}", err.to_string());
+            return app.fail(req, PasteError::Internal("couldn't read paste".to_string()));
+        }
+    };
+
+    let cache_control = match expires_at {
+        _ if views_remaining.is_some() => "no-store".to_string(),
+        Some(expires_at) => format!(
+            "max-age={}",
+            expires_at.saturating_sub(app.now).min(2629746)
+        ),
+        None => "max-age=2629746".to_string(),
+    };
+
+    // Only the raw view honours the extension's type, everything else is
+    // plain text so pasted markup never renders on this origin.
+    let content_type = match format {
+        Format::Raw => raw_content_type(path.extension()),
+        _ => mime::TEXT_PLAIN_UTF_8,
+    };
+
+    if let Some(count) = record.chunks {
+        // A view-limited paste may be deleted below, and JSON embeds the
+        // content, so those have to read the chunks up front. Everything
+        // else is streamed straight from the store.
+        if views_remaining.is_none() && format != Format::Json {
+            let reply = Reply::stream(content_type.as_ref(), chunks::chunk_keys(id, count));
+            return text_safety_headers(reply).with_header("Cache-Control", &cache_control);
         }
+
+        record.content = match chunks::read_all(app.store, id, count).await {
+            Ok(content) => content,
+            Err(err) => {
+                log_error!("error reading paste chunks: {:?}", err.to_string());
+                return app.fail(req, PasteError::Storage("couldn't read paste".to_string()));
+            }
+        };
+    }
+
+    // Without a language in the request the stored one is used, and without
+    // a usable one the paste is still escaped, as plain text.
+    let lang = requested_lang
+        .or_else(|| {
+            record
+                .language
+                .clone()
+                .filter(|lang| highlight::is_known_language(lang))
+        })
+        .unwrap_or_else(|| "txt".to_string());
+
+    let mut theme_cookie = None;
+
+    let reply = match (format, record.chunks) {
+        // Highlighting needs the whole document in memory, so large pastes
+        // are only ever served as plain text.
+        (Format::Page, None) => {
+            let (theme, picked) = match pick_theme(app, req) {
+                Ok(value) => value,
+                Err(err) => return app.fail(req, err),
+            };
+
+            if picked {
+                theme_cookie = Some(format!(
+                    "{}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+                    THEME_COOKIE,
+                    theme.id()
+                ));
+            }
+
+            let marked = LineRanges::parse(req.query("hl").unwrap_or_default());
+
+            // Markdown is rendered unless the source is asked for, and
+            // `?hl=` marks source lines, so it implies the source view.
+            let rendered = highlight::is_markdown(&lang)
+                && req.query("view") != Some("source")
+                && req.query("hl").is_none();
+
+            // Only the browser that created the paste holds its token.
+            let delete = delete_cookie_token(req, id).map(|_| format!("/{id}"));
+
+            let templates = app.templates();
+            let body = match rendered {
+                true => render_markdown(&templates, &record.content, expires_at, &theme, delete),
+                false => syntax_highlight_code(
+                    &templates,
+                    record.content,
+                    lang,
+                    expires_at,
+                    &theme,
+                    &marked,
+                    delete,
+                ),
+            };
+
+            let body = match body {
+                Ok(value) => value,
+                Err(error) => {
+                    log_error!("{:?}", error);
+                    return app.fail(
+                        req,
+                        PasteError::Internal("couldn't syntax highlight code".to_string()),
+                    );
+                }
+            };
+
+            Reply::html(200, body)
+        }
+        (Format::Ansi(colors), None) => {
+            let (theme, _) = match pick_theme(app, req) {
+                Ok(value) => value,
+                Err(err) => return app.fail(req, err),
+            };
+            let body = match syntax_highlight_ansi(&record.content, &lang, &theme, colors) {
+                Ok(value) => value,
+                Err(error) => {
+                    log_error!("{:?}", error);
+                    record.content
+                }
+            };
+
+            text_safety_headers(Reply::new(200, content_type.as_ref(), body))
+        }
+        (Format::Json, _) => Reply::json(
+            200,
+            &types::PasteView {
+                id: id.to_string(),
+                content: record.content,
+                language: record.language,
+                filename: record.filename,
+                created_at: record.created_at,
+                size: record.size,
+                expires_at,
+                views_remaining: views_remaining.map(|views| views - 1),
+                visibility,
+            },
+        ),
+        _ => text_safety_headers(Reply::new(200, content_type.as_ref(), record.content)),
+    };
+
+    if let (Some(metadata), Some(views)) = (&metadata, views_remaining) {
+        let consumed = consume_view(app, id, &stored.value, metadata, record.chunks, views).await;
+
+        if let Err(err) = consumed {
+            log_error!("error updating view budget: {:?}", err.to_string());
+            return app.fail(
+                req,
+                PasteError::Storage("couldn't update view count".to_string()),
+            );
+        }
+    }
+
+    let mut reply = reply.with_header("Cache-Control", &cache_control);
+
+    if format != Format::Raw {
+        reply = reply.with_header("Vary", "Accept, User-Agent, Cookie");
+    }
+
+    if visibility == types::Visibility::Unlisted {
+        reply = reply.with_header("X-Robots-Tag", "noindex, nofollow");
+    }
+
+    match theme_cookie {
+        Some(cookie) => reply.with_header("Set-Cookie", &cookie),
+        None => reply,
+    }
+}
+
+/// Paste content served as-is must never be sniffed into something the
+/// browser renders or runs.
+fn text_safety_headers(reply: Reply) -> Reply {
+    reply
+        .with_header("X-Content-Type-Options", "nosniff")
+        .with_header("Content-Security-Policy", "default-src 'none'; sandbox")
+}
+
+/// Remembers the theme last picked with `?theme=`.
+const THEME_COOKIE: &str = "theme";
+
+/// Picks the highlighting theme from `?theme=`, then the theme cookie, then
+/// the configured default. The flag is set when `?theme=` picked it, so the
+/// choice can be remembered.
+fn pick_theme(app: &App<'_>, req: &Incoming) -> Result<(ThemeChoice, bool), PasteError> {
+    let config = app.config;
+    let parse = |value: &str| ThemeChoice::parse(value, &config.light_theme, &config.dark_theme);
+
+    if let Some(value) = req.query("theme") {
+        return match parse(value) {
+            Some(choice) => Ok((choice, true)),
+            None => Err(PasteError::BadRequest(format!(
+                "unknown theme '{value}', see /api/themes"
+            ))),
+        };
+    }
+
+    let choice = req
+        .cookie(THEME_COOKIE)
+        .and_then(parse)
+        .or_else(|| parse(&config.theme))
+        .unwrap_or(ThemeChoice::Named(highlight::DEFAULT_THEME));
+
+    Ok((choice, false))
+}
+
+pub async fn list_themes(app: &App<'_>, _req: &Incoming) -> Reply {
+    Reply::json(
+        200,
+        &types::ThemeList {
+            default: app.config.theme.clone(),
+            light: app.config.light_theme.clone(),
+            dark: app.config.dark_theme.clone(),
+            themes: highlight::themes(),
+        },
+    )
+    .with_header("Cache-Control", "max-age=3600")
+}
+
+/// Every language a paste can be highlighted as, straight from the syntax
+/// set.
+pub async fn list_languages(_app: &App<'_>, _req: &Incoming) -> Reply {
+    Reply::json(
+        200,
+        &types::LanguageList {
+            languages: highlight::languages(),
+        },
+    )
+    .with_header("Cache-Control", "max-age=3600")
+}
+
+/// Looks for the deletion token in the `X-Delete-Token` header first and
+/// falls back to the `token` query parameter.
+fn presented_delete_token(req: &Incoming) -> Option<&str> {
+    req.header("x-delete-token").or_else(|| req.query("token"))
+}
+
+/// The token a request to delete `id` presents: the header or query
+/// parameter, else the cookie set when the paste was created from a
+/// browser.
+fn delete_token_for<'r>(req: &'r Incoming, id: &str) -> Option<&'r str> {
+    presented_delete_token(req).or_else(|| delete_cookie_token(req, id))
+}
+
+fn delete_cookie_token<'r>(req: &'r Incoming, id: &str) -> Option<&'r str> {
+    token::from_cookie(req.cookie(token::COOKIE)?, id)
+}
+
+pub async fn delete_paste(app: &App<'_>, req: &Incoming) -> Reply {
+    let id = match req.param("id").filter(|id| id::is_paste_id(id)) {
+        Some(value) => value,
+        None => return app.fail(req, PasteError::NotFound),
+    };
+
+    let (metadata, chunk_count) = match app.store.get(id).await {
+        Ok(Some(stored)) => {
+            let record = types::PasteRecord::decode(&stored.value, stored.metadata.as_ref());
+            let chunk_count = record.ok().and_then(|record| record.chunks);
+
+            (stored.metadata, chunk_count)
+        }
+        Ok(None) => return app.fail(req, PasteError::NotFound),
+        Err(err) => {
+            log_error!("error reading kv data: {:?}", err.to_string());
+            return app.fail(req, PasteError::Storage("couldn't read paste".to_string()));
+        }
+    };
+
+    let authorized = match (metadata, delete_token_for(req, id)) {
+        (Some(metadata), Some(token)) => token::verify(token, &metadata.delete_token_hash),
+        _ => false,
+    };
+
+    if !authorized {
+        return app.fail(
+            req,
+            PasteError::Forbidden("invalid delete token".to_string()),
+        );
+    }
+
+    match app.store.delete(id).await {
+        Ok(..) => {
+            if let Some(count) = chunk_count {
+                if let Err(err) = chunks::delete(app.store, id, count).await {
+                    log_error!("error deleting paste chunks: {:?}", err.to_string());
+                }
+            }
+
+            if let Err(err) = app.store.delete(&expiry::tombstone_key(id)).await {
+                log_error!("error deleting tombstone: {:?}", err.to_string());
+            }
+
+            Reply::message(200, "deleted")
+        }
+        Err(err) => {
+            log_error!("error deleting from KV: {:?}", err.to_string());
+            app.fail(
+                req,
+                PasteError::Storage("couldn't delete paste".to_string()),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::store::MemoryPasteStore;
+    use futures::executor::block_on;
+
+    pub(crate) const NOW: u64 = 1_700_000_000;
+
+    fn config() -> Config {
+        Config::from_lookup(|_| None)
+    }
+
+    pub(crate) fn store() -> MemoryPasteStore {
+        MemoryPasteStore::new(NOW)
+    }
+
+    pub(crate) fn app<'a>(store: &'a dyn PasteStore, config: &'a Config) -> App<'a> {
+        App {
+            store,
+            config,
+            now: NOW,
+        }
+    }
+
+    fn form(fields: &[(&str, &str)]) -> Incoming {
+        Incoming {
+            headers: vec![("accept".to_string(), "application/json".to_string())]
+                .into_iter()
+                .collect(),
+            form: fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), FormValue::Field(value.to_string())))
+                .collect(),
+            ..Incoming::default()
+        }
+    }
+
+    fn path(id: &str) -> Incoming {
+        Incoming {
+            params: vec![("id".to_string(), id.to_string())]
+                .into_iter()
+                .collect(),
+            ..Incoming::default()
+        }
+    }
+
+    /// A store that is down.
+    struct BrokenStore;
+
+    #[worker::async_trait::async_trait(?Send)]
+    impl PasteStore for BrokenStore {
+        async fn get(&self, _key: &str) -> anyhow::Result<Option<crate::store::StoredValue>> {
+            anyhow::bail!("kv unavailable")
+        }
+
+        async fn put(
+            &self,
+            _key: &str,
+            _value: String,
+            _options: PutOptions,
+        ) -> anyhow::Result<()> {
+            anyhow::bail!("kv unavailable")
+        }
+
+        async fn delete(&self, _key: &str) -> anyhow::Result<()> {
+            anyhow::bail!("kv unavailable")
+        }
+
+        async fn list(
+            &self,
+            _prefix: &str,
+            _cursor: Option<String>,
+            _limit: u64,
+        ) -> anyhow::Result<crate::store::ListPage> {
+            anyhow::bail!("kv unavailable")
+        }
+    }
+
+    fn json(body: &str) -> Incoming {
+        Incoming {
+            headers: vec![("content-type".to_string(), "application/json".to_string())]
+                .into_iter()
+                .collect(),
+            body: body.as_bytes().to_vec(),
+            ..Incoming::default()
+        }
+    }
+
+    fn create(app: &App, fields: &[(&str, &str)]) -> types::CodePaseResponse {
+        let reply = block_on(create_paste(app, form(fields)));
+        assert_eq!(reply.status, 200, "{}", reply.body);
+
+        serde_json::from_str(&reply.body).unwrap()
+    }
+
+    #[test]
+    fn errors_have_codes_and_statuses() {
+        let store = store();
+        let config = config();
+        let broken = app(&BrokenStore, &config);
+        let app = app(&store, &config);
+        let error = |reply: Reply| {
+            let response: types::ErrorResponse = serde_json::from_str(&reply.body).unwrap();
+            (reply.status, response.code)
+        };
+
+        assert_eq!(
+            error(block_on(get_paste(&app, &path("nothing")))),
+            (404, "not_found".to_string())
+        );
+        assert_eq!(
+            error(block_on(delete_paste(&app, &path("nothing")))),
+            (404, "not_found".to_string())
+        );
+
+        let mut browser = path("nothing");
+        browser
+            .headers
+            .insert("accept".to_string(), "text/html".to_string());
+        let page = block_on(get_paste(&app, &browser));
+        assert_eq!(page.status, 404);
+        assert!(page.body.contains("<h1>404</h1>"));
+        assert!(page.body.contains("not_found"));
+        assert!(page.body.contains("<style>"));
+
+        // Failures of the store itself are worth retrying, not a 404.
+        let unavailable = (503, "storage_unavailable".to_string());
+        assert_eq!(
+            error(block_on(get_paste(&broken, &path("abc")))),
+            unavailable
+        );
+        assert_eq!(
+            error(block_on(delete_paste(&broken, &path("abc")))),
+            unavailable
+        );
+        assert_eq!(
+            error(block_on(create_paste(&broken, form(&[("content", "x")])))),
+            unavailable
+        );
+    }
+
+    #[test]
+    fn panics_become_internal_errors() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let reply = block_on(guarded(&app, &Incoming::default(), async {
+            panic!("handler bug");
+        }));
+        assert_eq!(reply.status, 500);
+        let response: types::ErrorResponse = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(response.code, "internal");
+
+        let mut browser = Incoming::default();
+        browser
+            .headers
+            .insert("accept".to_string(), "text/html".to_string());
+        let page = block_on(guarded(&app, &browser, async {
+            panic!("handler bug");
+        }));
+        assert_eq!(page.status, 500);
+        assert!(page.body.contains("the server hit an unexpected error"));
+
+        let fine = block_on(guarded(&app, &browser, async { Reply::message(200, "ok") }));
+        assert_eq!(fine.status, 200);
+    }
+
+    #[test]
+    fn created_paste_can_be_read_back() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "fn main() {}")]);
+        let reply = block_on(raw_paste(&app, &path(&created.id)));
+
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.body, "fn main() {}");
+    }
+
+    #[test]
+    fn generated_ids_never_overwrite_pastes() {
+        let store = store();
+        let config = Config::from_lookup(|name| match name {
+            "PASTE_ID_LENGTH" => Some("1".to_string()),
+            "PASTE_ID_ALPHABET" => Some("ab".to_string()),
+            _ => None,
+        });
+        let app = app(&store, &config);
+
+        block_on(store.put("a", "taken".to_string(), PutOptions::default())).unwrap();
+
+        assert_eq!(create(&app, &[("content", "new")]).id, "b");
+        assert_eq!(block_on(raw_paste(&app, &path("a"))).body, "taken");
+
+        // With every id taken the paste is refused rather than overwriting.
+        let full = block_on(create_paste(&app, form(&[("content", "newer")])));
+        assert_eq!(full.status, 503);
+        assert_eq!(block_on(raw_paste(&app, &path("b"))).body, "new");
+    }
+
+    #[test]
+    fn json_pastes_return_the_stored_record() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let body = r#"{
+            "content": "fn main() {}",
+            "language": "Rust",
+            "filename": "main.rs",
+            "expiry": "1d",
+            "visibility": "unlisted",
+            "max_views": 3
+        }"#;
+
+        for reply in [
+            block_on(create_paste_json(&app, json(body))),
+            block_on(create_paste(&app, json(body))),
+        ] {
+            assert_eq!(reply.status, 201, "{}", reply.body);
+
+            let created: types::CodePaseResponse = serde_json::from_str(&reply.body).unwrap();
+            assert_eq!(created.language.as_deref(), Some("rs"));
+            assert_eq!(created.filename.as_deref(), Some("main.rs"));
+            assert_eq!(created.created_at, Some(NOW));
+            assert_eq!(created.size, 12);
+            assert_eq!(created.expires_at, Some(NOW + 86400));
+            assert_eq!(created.views_remaining, Some(3));
+            assert_eq!(created.visibility, types::Visibility::Unlisted);
+
+            let mut req = path(&created.id);
+            req.headers
+                .insert("accept".to_string(), "application/json".to_string());
+            let read = block_on(reveal_paste(&app, &req));
+            assert!(read
+                .headers
+                .contains(&("X-Robots-Tag".to_string(), "noindex, nofollow".to_string())));
+
+            let view: types::PasteView = serde_json::from_str(&read.body).unwrap();
+            assert_eq!(view.content, "fn main() {}");
+            assert_eq!(view.visibility, types::Visibility::Unlisted);
+        }
+    }
+
+    #[test]
+    fn raw_bodies_become_pastes() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let upload = |reply: Reply| {
+            assert_eq!(reply.status, 201, "{}", reply.body);
+            let link = reply.body.trim_end().to_string();
+            let token = reply
+                .headers
+                .iter()
+                .find(|(name, _)| name == "X-Delete-Token")
+                .map(|(_, value)| value.clone())
+                .unwrap();
+            (link, token)
+        };
+
+        // What `curl --data-binary @script.py` sends.
+        let posted = |body: &[u8]| Incoming {
+            headers: vec![(
+                "content-type".to_string(),
+                "application/x-www-form-urlencoded".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+            query: vec![("expiry".to_string(), "1h".to_string())],
+            body: body.to_vec(),
+            ..Incoming::default()
+        };
+        let script = posted(b"#!/usr/bin/env python3\nprint('a=b&c')\n");
+        let (link, token) = upload(block_on(create_paste(&app, script)));
+        assert!(link.ends_with(".py"), "{}", link);
+
+        let id = link.rsplit('/').next().unwrap().trim_end_matches(".py");
+        let metadata = block_on(store.get(id)).unwrap().unwrap().metadata.unwrap();
+        assert_eq!(metadata.expires_at, Some(NOW + 3600));
+        assert!(token::verify(&token, &metadata.delete_token_hash));
+
+        let reply = block_on(raw_paste(&app, &path(id)));
+        assert_eq!(reply.body, "#!/usr/bin/env python3\nprint('a=b&c')\n");
+
+        // `curl -T Makefile`
+        let put = Incoming {
+            params: vec![("filename".to_string(), "Makefile".to_string())]
+                .into_iter()
+                .collect(),
+            body: b"all:\n\tcargo build\n".to_vec(),
+            ..Incoming::default()
+        };
+        let (link, _) = upload(block_on(put_paste(&app, put)));
+        assert!(link.ends_with(".Makefile"), "{}", link);
+
+        let binary = posted(&[0xff, 0xfe]);
+        assert_eq!(block_on(create_paste(&app, binary)).status, 400);
+    }
+
+    #[test]
+    fn permalinks_use_the_configured_or_request_origin() {
+        let store = store();
+        let unset = config();
+        let configured = Config::from_lookup(|name| match name {
+            "PASTE_BASE_URL" => Some("https://paste.example.com/".to_string()),
+            _ => None,
+        });
+        let link = |config: &Config| {
+            let mut req = form(&[("content", "hello")]);
+            req.origin = "http://localhost:8787".to_string();
+
+            let app = app(&store, config);
+            let reply = block_on(create_paste(&app, req));
+            serde_json::from_str::<types::CodePaseResponse>(&reply.body)
+                .unwrap()
+                .permalink
+        };
+
+        let permalink = link(&configured);
+        assert!(
+            permalink.starts_with("https://paste.example.com/"),
+            "{}",
+            permalink
+        );
+        assert!(!permalink.contains(".com//"));
+        assert!(link(&unset).starts_with("http://localhost:8787/"));
+
+        // Browsers are redirected relative to wherever they posted.
+        let app = app(&store, &configured);
+        let redirect = block_on(create_paste(
+            &app,
+            Incoming {
+                form: vec![
+                    ("content".to_string(), FormValue::Field("x = 1".to_string())),
+                    ("language".to_string(), FormValue::Field("py".to_string())),
+                ]
+                .into_iter()
+                .collect(),
+                ..Incoming::default()
+            },
+        ));
+        assert_eq!(redirect.status, 302);
+        let location = redirect
+            .headers
+            .iter()
+            .find(|(name, _)| name == "Location")
+            .map(|(_, value)| value.as_str())
+            .unwrap();
+        assert!(location.starts_with('/') && location.ends_with(".py"));
+    }
+
+    #[test]
+    fn json_pastes_report_every_invalid_field() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let reply = block_on(create_paste_json(
+            &app,
+            json(
+                r#"{
+                    "language": "klingon",
+                    "filename": "../etc/passwd",
+                    "expiry": "soon",
+                    "visibility": "secret",
+                    "burn_after_read": true,
+                    "max_views": 2,
+                    "slug": "API"
+                }"#,
+            ),
+        ));
+        assert_eq!(reply.status, 400);
+
+        let invalid: types::ErrorResponse = serde_json::from_str(&reply.body).unwrap();
+        let fields: Vec<_> = invalid
+            .errors
+            .iter()
+            .map(|error| error.field.as_str())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "content",
+                "language",
+                "filename",
+                "expiry",
+                "visibility",
+                "max_views",
+                "slug"
+            ]
+        );
+
+        assert_eq!(block_on(create_paste_json(&app, json("{"))).status, 400);
+        assert_eq!(
+            block_on(create_paste_json(&app, form(&[("content", "x")]))).status,
+            415
+        );
+    }
+
+    #[test]
+    fn pasted_markup_is_escaped_or_served_as_text() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let header = |reply: &Reply, name: &str| {
+            reply
+                .headers
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let created = create(&app, &[("content", "<script>alert(1)</script>")]);
+
+        let page = block_on(get_paste(&app, &path(&created.id)));
+        assert!(!page.body.contains("<script>alert"));
+        assert!(page.body.contains("&lt;script&gt;"));
+
+        let raw = block_on(raw_paste(&app, &path(&format!("{}.html", created.id))));
+        assert_eq!(raw.body, "<script>alert(1)</script>");
+        assert_eq!(
+            header(&raw, "Content-Type").as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            header(&raw, "X-Content-Type-Options").as_deref(),
+            Some("nosniff")
+        );
+
+        let json = block_on(raw_paste(&app, &path(&format!("{}.json", created.id))));
+        assert_eq!(
+            header(&json, "Content-Type").as_deref(),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn missing_paste_is_not_found() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        assert_eq!(block_on(get_paste(&app, &path("nope"))).status, 404);
+    }
+
+    #[test]
+    fn pastes_are_stored_as_versioned_records() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "echo hi"), ("language", "sh")]);
+
+        let stored = block_on(store.get(&created.id)).unwrap().unwrap();
+        let record: types::PasteRecord = serde_json::from_str(&stored.value).unwrap();
+        assert_eq!(record.version, types::PASTE_RECORD_VERSION);
+        assert_eq!(record.content, "echo hi");
+        assert_eq!(record.created_at, Some(NOW));
+
+        // Listing reads the metadata alone, without the values.
+        let page = block_on(store.list("", None, 10)).unwrap();
+        let listed = page
+            .keys
+            .iter()
+            .find(|key| key.name == created.id)
+            .and_then(|key| key.metadata.as_ref())
+            .unwrap();
+        assert_eq!(listed.version, types::PASTE_RECORD_VERSION);
+        assert_eq!(listed.language.as_deref(), Some("sh"));
+        assert_eq!(listed.size, 7);
+        assert_eq!(listed.created_at, Some(NOW));
+    }
+
+    #[test]
+    fn legacy_raw_values_are_still_served() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        block_on(store.put("legacy1", "echo hi".to_string(), PutOptions::default())).unwrap();
+        let reply = block_on(raw_paste(&app, &path("legacy1")));
+
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.body, "echo hi");
+    }
+
+    #[test]
+    fn delete_requires_the_owner_token() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "secret")]);
+
+        let mut wrong = path(&created.id);
+        wrong.query = vec![("token".to_string(), "guess".to_string())];
+        assert_eq!(block_on(delete_paste(&app, &wrong)).status, 403);
+
+        let mut right = path(&created.id);
+        right
+            .headers
+            .insert("x-delete-token".to_string(), created.delete_token);
+        assert_eq!(block_on(delete_paste(&app, &right)).status, 200);
+        assert_eq!(block_on(get_paste(&app, &path(&created.id))).status, 404);
+    }
+
+    #[test]
+    fn delete_is_forbidden_without_the_right_token() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "secret")]);
+        let other = create(&app, &[("content", "other")]);
+        let delete = |token: Option<&str>| {
+            let mut req = path(&created.id);
+            if let Some(token) = token {
+                req.query = vec![("token".to_string(), token.to_string())];
+            }
+            block_on(delete_paste(&app, &req)).status
+        };
+
+        assert_eq!(delete(None), 403);
+        assert_eq!(delete(Some(&other.delete_token)), 403);
+        assert_eq!(block_on(raw_paste(&app, &path(&created.id))).body, "secret");
+
+        assert_eq!(delete(Some(&created.delete_token)), 200);
+        assert_eq!(delete(Some(&created.delete_token)), 404);
+    }
+
+    #[test]
+    fn browsers_delete_with_their_cookie() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = block_on(create_paste(
+            &app,
+            Incoming {
+                form: vec![("content".to_string(), FormValue::Field("oops".to_string()))]
+                    .into_iter()
+                    .collect(),
+                ..Incoming::default()
+            },
+        ));
+        let set_cookie = created
+            .headers
+            .iter()
+            .find(|(name, _)| name == "Set-Cookie")
+            .map(|(_, value)| value.clone())
+            .unwrap();
+        assert!(
+            set_cookie.contains("; Path=/; Max-Age=2592000;"),
+            "{}",
+            set_cookie
+        );
+
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        let id = cookie
+            .trim_start_matches("delete_tokens=")
+            .split_once(':')
+            .unwrap()
+            .0
+            .to_string();
+
+        let stranger = block_on(get_paste(&app, &path(&id)));
+        assert!(!stranger.body.contains("Delete paste"));
+
+        let mut owner = path(&id);
+        owner.headers.insert("cookie".to_string(), cookie);
+        let page = block_on(get_paste(&app, &owner));
+        assert!(page.body.contains(&format!(r#"data-path="/{id}""#)));
+        assert!(page
+            .body
+            .contains("fetch(this.dataset.path, { method: 'DELETE' })"));
+
+        let mut other = path(&id);
+        other
+            .headers
+            .insert("cookie".to_string(), format!("delete_tokens={id}:guess"));
+        assert_eq!(block_on(delete_paste(&app, &other)).status, 403);
+
+        assert_eq!(block_on(delete_paste(&app, &owner)).status, 200);
+        assert_eq!(block_on(get_paste(&app, &path(&id))).status, 404);
+    }
+
+    #[test]
+    fn expired_paste_is_gone() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "temporary"), ("expiry", "10m")]);
+        assert_eq!(created.expires_at, Some(NOW + 600));
+
+        store.set_now(NOW + 601);
+        let later = App {
+            now: NOW + 601,
+            ..app
+        };
+
+        let gone = block_on(get_paste(&later, &path(&created.id)));
+        assert_eq!(gone.status, 410);
+        assert!(
+            gone.body.contains("paste expired at 2023-11-14"),
+            "{}",
+            gone.body
+        );
+    }
+
+    #[test]
+    fn absurd_expiries_are_rejected() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        for expiry in ["18446744073709551615s", "5300w"] {
+            let reply = block_on(create_paste(
+                &app,
+                form(&[("content", "x"), ("expiry", expiry)]),
+            ));
+            assert_eq!(reply.status, 400, "{}", reply.body);
+
+            let reply = block_on(create_paste_json(
+                &app,
+                json(&format!(r#"{{"content": "x", "expiry": "{expiry}"}}"#)),
+            ));
+            let invalid: types::ErrorResponse = serde_json::from_str(&reply.body).unwrap();
+            assert_eq!(invalid.errors[0].field, "expiry");
+        }
+
+        let created = create(&app, &[("content", "x"), ("expiry", "5200w")]);
+        assert_eq!(created.expires_at, Some(NOW + 5200 * 604_800));
+    }
+
+    #[test]
+    fn burn_after_read_needs_confirmation_and_deletes() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "hunter2"), ("burn_after_read", "on")]);
+
+        let preview = block_on(get_paste(&app, &path(&created.id)));
+        assert_eq!(preview.status, 200);
+        assert!(!preview.body.contains("hunter2"));
+
+        let revealed = block_on(reveal_paste(&app, &path(&created.id)));
+        assert!(revealed.body.contains("hunter2"));
+
+        let gone = block_on(get_paste(&app, &path(&created.id)));
+        assert_eq!(gone.status, 410);
+        assert!(
+            gone.body.contains("this paste was burned after being read"),
+            "{}",
+            gone.body
+        );
+    }
+
+    #[test]
+    fn view_budgets_are_spent_one_post_at_a_time() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "token"), ("max_views", "3")]);
+        let views_remaining = || {
+            block_on(store.get(&created.id))
+                .unwrap()
+                .and_then(|stored| stored.metadata)
+                .and_then(|metadata| metadata.views_remaining)
+        };
+
+        for remaining in [2, 1] {
+            let preview = block_on(get_paste(&app, &path(&created.id)));
+            assert!(!preview.body.contains("token"));
+
+            let read = block_on(reveal_raw_paste(&app, &path(&created.id)));
+            assert_eq!(read.body, "token");
+            assert_eq!(views_remaining(), Some(remaining));
+        }
+
+        assert_eq!(
+            block_on(reveal_raw_paste(&app, &path(&created.id))).body,
+            "token"
+        );
+        assert!(block_on(store.get(&created.id)).unwrap().is_none());
+        assert_eq!(
+            block_on(reveal_raw_paste(&app, &path(&created.id))).status,
+            410
+        );
+    }
+
+    #[test]
+    fn api_clients_are_told_to_post_for_view_limited_pastes() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let created = create(&app, &[("content", "hunter2"), ("max_views", "2")]);
+
+        let mut api = path(&created.id);
+        api.headers
+            .insert("accept".to_string(), "application/json".to_string());
+        let mut curl = path(&created.id);
+        curl.headers
+            .insert("user-agent".to_string(), "curl/8.4.0".to_string());
+
+        for reply in [
+            block_on(get_paste(&app, &api)),
+            block_on(get_paste(&app, &curl)),
+            block_on(raw_paste(&app, &curl)),
+        ] {
+            assert_eq!(reply.status, 409);
+            let error: types::ErrorResponse = serde_json::from_str(&reply.body).unwrap();
+            assert_eq!(error.code, "confirmation_required");
+            assert!(error.message.contains("POST to /"), "{}", error.message);
+        }
+
+        let view: types::PasteView =
+            serde_json::from_str(&block_on(reveal_paste(&app, &api)).body).unwrap();
+        assert_eq!(view.content, "hunter2");
+        assert_eq!(view.views_remaining, Some(1));
+    }
+
+    #[test]
+    fn large_pastes_are_chunked_and_streamed() {
+        let store = store();
+        let config = Config::from_lookup(|name| match name {
+            "PASTE_CHUNK_SIZE" => Some("4".to_string()),
+            _ => None,
+        });
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "0123456789")]);
+        let reply = block_on(get_paste(&app, &path(&created.id)));
+        assert!(!reply
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-length")));
+        let keys = reply.stream.unwrap();
+
+        assert_eq!(keys.len(), 3);
+        let chunks: Vec<String> = keys
+            .iter()
+            .map(|key| block_on(store.get(key)).unwrap().unwrap().value)
+            .collect();
+        assert_eq!(chunks.concat(), "0123456789");
+
+        let mut delete = path(&created.id);
+        delete
+            .headers
+            .insert("x-delete-token".to_string(), created.delete_token);
+        assert_eq!(block_on(delete_paste(&app, &delete)).status, 200);
+        assert!(block_on(store.get(&keys[0])).unwrap().is_none());
+    }
+
+    #[test]
+    fn internal_keys_are_not_served_as_pastes() {
+        let store = store();
+        let config = Config::from_lookup(|name| match name {
+            "PASTE_CHUNK_SIZE" => Some("4".to_string()),
+            _ => None,
+        });
+        let app = app(&store, &config);
+
+        let created = create(
+            &app,
+            &[
+                ("content", "0123456789"),
+                ("max_views", "1"),
+                ("expiry", "1h"),
+                ("slug", "launch-codes"),
+            ],
+        );
+
+        for key in [
+            chunks::chunk_key(&created.id, 0),
+            expiry::tombstone_key(&created.id),
+            slug::reservation_key(&created.id),
+        ] {
+            assert!(block_on(store.get(&key)).unwrap().is_some(), "{}", key);
+            assert_eq!(block_on(reveal_raw_paste(&app, &path(&key))).status, 404);
+
+            let mut delete = path(&key);
+            delete
+                .headers
+                .insert("x-delete-token".to_string(), created.delete_token.clone());
+            assert_eq!(block_on(delete_paste(&app, &delete)).status, 404);
+        }
+
+        let view = block_on(reveal_raw_paste(&app, &path(&created.id)));
+        assert_eq!(view.body, "0123456789");
+    }
+
+    #[test]
+    fn oversized_uploads_are_rejected() {
+        let store = store();
+        let config = Config::from_lookup(|name| match name {
+            "PASTE_MAX_SIZE" => Some("4".to_string()),
+            _ => None,
+        });
+        let app = app(&store, &config);
+
+        let reply = block_on(create_paste(&app, form(&[("content", "12345")])));
+        assert_eq!(reply.status, 413);
+        assert!(reply.body.contains("exceeds 4 bytes"));
+
+        let mut browser = Incoming {
+            body_too_large: true,
+            ..Incoming::default()
+        };
+        browser
+            .headers
+            .insert("accept".to_string(), "text/html".to_string());
+        let reply = block_on(create_paste(&app, browser));
+        assert_eq!(reply.status, 413);
+        assert!(reply.body.contains("<h1>413</h1>"));
+
+        let limits: types::Limits =
+            serde_json::from_str(&block_on(discovery(&app, &Incoming::default())).body).unwrap();
+        assert_eq!(limits.max_paste_size, 4);
+        assert_eq!(limits.max_request_size, config.max_request_size);
+    }
+
+    #[test]
+    fn slugs_are_validated_and_owned() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let bad = block_on(create_paste(
+            &app,
+            form(&[("content", "x"), ("slug", "api")]),
+        ));
+        assert_eq!(bad.status, 400);
+
+        let fields = [("content", "step 1"), ("slug", "deploy-checklist")];
+        let created = create(&app, &fields);
+        assert_eq!(created.id, "deploy-checklist");
+
+        let taken = block_on(create_paste(&app, form(&fields)));
+        assert_eq!(taken.status, 409);
+
+        let mut delete = path(&created.id);
+        delete
+            .headers
+            .insert("x-delete-token".to_string(), created.delete_token.clone());
+        assert_eq!(block_on(delete_paste(&app, &delete)).status, 200);
+
+        // The slug stays with its owner after the paste is gone.
+        let stranger = block_on(create_paste(&app, form(&fields)));
+        assert_eq!(stranger.status, 409);
+
+        let mut owner = form(&fields);
+        owner
+            .headers
+            .insert("x-delete-token".to_string(), created.delete_token);
+        assert_eq!(block_on(create_paste(&app, owner)).status, 200);
+    }
+
+    #[test]
+    fn owners_can_reserve_slugs_ahead() {
+        let store = store();
+        let config = Config::from_lookup(|name| match name {
+            "PASTE_OWNER_TOKENS" => Some("docs-team-token, ops-token".to_string()),
+            _ => None,
+        });
+        let app = app(&store, &config);
+        let reserve = |token: &str| {
+            let mut req = Incoming::default();
+            req.params
+                .insert("slug".to_string(), "onboarding".to_string());
+            if !token.is_empty() {
+                req.headers
+                    .insert("x-delete-token".to_string(), token.to_string());
+            }
+            block_on(reserve_slug(&app, &req)).status
+        };
+
+        // Any made-up token used to pass, now only the operator's do.
+        assert_eq!(reserve(""), 401);
+        assert_eq!(reserve(&token::generate()), 401);
+
+        assert_eq!(reserve("ops-token"), 201);
+        assert_eq!(reserve("ops-token"), 200);
+        assert_eq!(reserve("docs-team-token"), 409);
+
+        let fields = [("content", "welcome"), ("slug", "onboarding")];
+        assert_eq!(block_on(create_paste(&app, form(&fields))).status, 409);
+
+        let mut owner = form(&fields);
+        owner
+            .headers
+            .insert("x-delete-token".to_string(), "ops-token".to_string());
+        let reply = block_on(create_paste(&app, owner));
+        let created: types::CodePaseResponse = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(created.delete_token, "ops-token");
+    }
+
+    #[test]
+    fn slug_reservations_expire() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let ttl = config.slug_reservation_ttl.unwrap();
+
+        let fields = [
+            ("content", "v1"),
+            ("slug", "release-notes"),
+            ("expiry", "1d"),
+        ];
+        let created = create(&app, &fields);
+
+        let reservation = &block_on(store.list("slug:", None, 10)).unwrap().keys[0];
+        assert_eq!(reservation.name, slug::reservation_key(&created.id));
+        assert_eq!(reservation.expires_at, Some(NOW + 86400 + ttl));
+
+        // Held past the paste's expiry, then free for anyone.
+        let later = NOW + 86400 + ttl - 1;
+        store.set_now(later);
+        let app = App { now: later, ..app };
+        assert_eq!(block_on(create_paste(&app, form(&fields))).status, 409);
+
+        let later = NOW + 86400 + ttl + 1;
+        store.set_now(later);
+        let app = App { now: later, ..app };
+        assert_ne!(create(&app, &fields).delete_token, created.delete_token);
+    }
+
+    #[test]
+    fn themes_can_be_picked_and_remembered() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let created = create(&app, &[("content", "fn main() {}")]);
+        let highlighted = |query: &str, cookie: &str| {
+            let mut req = path(&format!("{}.rs", created.id));
+            if !query.is_empty() {
+                req.query.push(("theme".to_string(), query.to_string()));
+            }
+            if !cookie.is_empty() {
+                req.headers.insert("cookie".to_string(), cookie.to_string());
+            }
+            block_on(get_paste(&app, &req))
+        };
+
+        let default = highlighted("", "");
+        let light = highlighted("inspiredgithub", "");
+        assert_ne!(default.body, light.body);
+        assert!(light.headers.contains(&(
+            "Set-Cookie".to_string(),
+            "theme=inspiredgithub; Path=/; Max-Age=31536000; SameSite=Lax".to_string()
+        )));
+
+        let remembered = highlighted("", "lang=en; theme=inspiredgithub");
+        assert_eq!(remembered.body, light.body);
+
+        assert!(highlighted("auto", "")
+            .body
+            .contains("prefers-color-scheme"));
+        assert_eq!(highlighted("bogus", "").status, 400);
+
+        let themes: types::ThemeList =
+            serde_json::from_str(&block_on(list_themes(&app, &Incoming::default())).body).unwrap();
+        assert!(themes
+            .themes
+            .iter()
+            .any(|theme| theme.id == "solarized-dark" && theme.dark));
+    }
+
+    #[test]
+    fn index_offers_the_languages_the_api_lists() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let list: types::LanguageList =
+            serde_json::from_str(&block_on(list_languages(&app, &Incoming::default())).body)
+                .unwrap();
+        let rust = list
+            .languages
+            .iter()
+            .find(|language| language.name == "Rust")
+            .unwrap();
+        assert!(rust.extensions.contains(&"rs".to_string()));
+
+        let index = block_on(index(&app, &Incoming::default()));
+        assert_eq!(index.status, 200);
+        assert!(index.body.contains(r#"<option value="rs">Rust</option>"#));
+        assert!(index.body.contains(r#"<option selected="" value="">"#));
+        for language in &list.languages {
+            assert!(index
+                .body
+                .contains(&format!(r#"<option value="{}">"#, language.id)));
+        }
+    }
+
+    #[test]
+    fn reads_are_negotiated() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let created = create(&app, &[("content", "echo hi"), ("language", "sh")]);
+        let read = |headers: &[(&str, &str)]| {
+            let mut req = path(&created.id);
+            for (name, value) in headers {
+                req.headers.insert(name.to_string(), value.to_string());
+            }
+            block_on(get_paste(&app, &req))
+        };
+
+        let page = read(&[("accept", "text/html,application/xhtml+xml,*/*;q=0.8")]);
+        assert!(page.body.contains("<pre"));
+
+        let curl = read(&[("user-agent", "curl/8.4.0"), ("accept", "*/*")]);
+        assert_eq!(curl.body, "echo hi");
+
+        let mut ansi = path(&format!("{}.sh", created.id));
+        ansi.headers
+            .insert("user-agent".to_string(), "curl/8.4.0".to_string());
+        let ansi = block_on(get_paste(&app, &ansi));
+        assert!(ansi.body.contains("\x1b[38;2;"));
+
+        let mut palette = path(&created.id);
+        palette
+            .query
+            .push(("format".to_string(), "ansi256".to_string()));
+        let palette = block_on(get_paste(&app, &palette));
+        assert!(palette.body.contains("\x1b[38;5;"));
+
+        let curl_html = read(&[("user-agent", "curl/8.4.0"), ("accept", "text/html")]);
+        assert!(curl_html.body.contains("<pre"));
+
+        let json = read(&[("accept", "text/plain;q=0.5, application/json")]);
+        let view: types::PasteView = serde_json::from_str(&json.body).unwrap();
+        assert_eq!(view.content, "echo hi");
+        assert_eq!(view.language.as_deref(), Some("sh"));
+
+        assert_eq!(read(&[("accept", "image/png")]).status, 406);
+    }
+
+    #[test]
+    fn form_languages_are_checked_like_json_ones() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(&app, &[("content", "fn main() {}"), ("language", "Rust")]);
+        assert_eq!(created.language.as_deref(), Some("rs"));
+
+        let unknown = block_on(create_paste(
+            &app,
+            form(&[("content", "x"), ("language", "klingon")]),
+        ));
+        assert_eq!(unknown.status, 400);
+        assert!(unknown.body.contains("/api/languages"), "{}", unknown.body);
+
+        let upload = Incoming {
+            query: vec![("language".to_string(), "klingon".to_string())],
+            body: b"x".to_vec(),
+            ..Incoming::default()
+        };
+        assert_eq!(block_on(create_paste(&app, upload)).status, 400);
+    }
+
+    #[test]
+    fn detected_language_is_stored_and_rendered() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+
+        let created = create(
+            &app,
+            &[("content", "#!/usr/bin/env python3\nprint('hi')\n")],
+        );
+        let stored = block_on(store.get(&created.id)).unwrap().unwrap();
+        assert_eq!(stored.metadata.unwrap().language.as_deref(), Some("py"));
+
+        let page = block_on(get_paste(&app, &path(&created.id)));
+        assert!(page.body.contains("<title>py code</title>"));
+    }
+
+    #[test]
+    fn paths_select_the_language() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let created = create(
+            &app,
+            &[("content", "all:\n\techo hi\n"), ("language", "txt")],
+        );
+        let title = |req: Incoming| {
+            let body = block_on(get_paste(&app, &req)).body;
+            body.lines()
+                .find(|line| line.contains("<title>"))
+                .map(|line| line.trim().to_string())
+        };
+
+        let mut by_filename = path(&created.id);
+        by_filename
+            .params
+            .insert("filename".to_string(), "Makefile".to_string());
+        assert_eq!(
+            title(by_filename).as_deref(),
+            Some("<title>Makefile code</title>")
+        );
+
+        let multi_dot = path(&format!("{}.html.erb", created.id));
+        assert_eq!(
+            title(multi_dot).as_deref(),
+            Some("<title>html.erb code</title>")
+        );
+
+        let mut overridden = path(&format!("{}.rs", created.id));
+        overridden
+            .query
+            .push(("lang".to_string(), "Python".to_string()));
+        assert_eq!(title(overridden).as_deref(), Some("<title>py code</title>"));
+
+        let mut unknown = path(&created.id);
+        unknown
+            .query
+            .push(("lang".to_string(), "klingon".to_string()));
+        assert_eq!(block_on(get_paste(&app, &unknown)).status, 400);
+    }
+
+    #[test]
+    fn markdown_is_rendered_with_a_source_toggle() {
+        let store = store();
+        let config = config();
+        let app = app(&store, &config);
+        let created = create(
+            &app,
+            &[("content", "# Deploy\n\n<b>x</b>\n"), ("language", "md")],
+        );
+
+        let rendered = block_on(get_paste(&app, &path(&created.id)));
+        assert!(rendered.body.contains("<h1>Deploy</h1>"));
+        assert!(rendered.body.contains("?view=source"));
+        assert!(!rendered.body.contains("<b>x</b>"));
+
+        let mut source = path(&created.id);
+        source
+            .query
+            .push(("view".to_string(), "source".to_string()));
+        let source = block_on(get_paste(&app, &source));
+        assert!(!source.body.contains("<h1>"));
+        assert!(source.body.contains("?view=rendered"));
     }
 }
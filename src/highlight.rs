@@ -0,0 +1,588 @@
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use syntect::dumps::from_binary;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    css_for_theme_with_class_style, line_tokens_to_classed_spans, start_highlighted_html_snippet,
+    styled_line_to_highlighted_html, ClassStyle, IncludeBackground,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+use crate::types;
+
+// Both sets are dumped by `build.rs`. Keeping them in statics means an
+// isolate deserializes them once, and the regexes of a syntax are compiled
+// the first time it is used and then reused by every later request.
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(|| {
+    from_binary(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/syntaxes.packdump"
+    )))
+});
+
+static THEME_SET: Lazy<ThemeSet> = Lazy::new(|| {
+    from_binary(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/themes.themedump"
+    )))
+});
+
+pub fn syntax_set() -> &'static SyntaxSet {
+    &SYNTAX_SET
+}
+
+pub fn theme_set() -> &'static ThemeSet {
+    &THEME_SET
+}
+
+pub fn is_markdown(lang: &str) -> bool {
+    syntax_set()
+        .find_syntax_by_extension(lang)
+        .map_or(false, |syntax| syntax.name == "Markdown")
+}
+
+/// Whether `lang` names a syntax, as an extension.
+pub fn is_known_language(lang: &str) -> bool {
+    syntax_set().find_syntax_by_extension(lang).is_some()
+}
+
+/// Finds the token that selects a syntax for an extension or file name.
+/// Whole names come first, so `Makefile` or `config.ru` match as listed,
+/// then ever shorter suffixes: `index.html.erb` tries `html.erb`, then
+/// `erb`.
+pub fn resolve_language(candidate: &str) -> Option<String> {
+    let mut rest = candidate;
+
+    loop {
+        if is_known_language(rest) {
+            return Some(rest.to_string());
+        }
+
+        rest = rest.split_once('.')?.1;
+    }
+}
+
+/// Like [`resolve_language`], but also takes syntax names such as `Rust`,
+/// for `?lang=`.
+pub fn resolve_language_override(value: &str) -> Option<String> {
+    resolve_language(value).or_else(|| {
+        syntax_set()
+            .find_syntax_by_token(value)
+            .and_then(|syntax| syntax.file_extensions.first().cloned())
+    })
+}
+
+/// Every syntax that can be picked for a paste, by name. Hidden syntaxes
+/// and those without an extension that leads back to them are left out,
+/// since a paste only stores the extension.
+pub fn languages() -> Vec<types::LanguageInfo> {
+    let syntaxes = syntax_set();
+    let mut languages: Vec<_> = syntaxes
+        .syntaxes()
+        .iter()
+        .filter(|syntax| !syntax.hidden)
+        .filter_map(|syntax| {
+            let id = syntax.file_extensions.iter().find(|ext| {
+                syntaxes
+                    .find_syntax_by_extension(ext)
+                    .map_or(false, |found| found.name == syntax.name)
+            })?;
+
+            Some(types::LanguageInfo {
+                id: id.clone(),
+                name: syntax.name.clone(),
+                extensions: syntax.file_extensions.clone(),
+                first_line: syntax.first_line_match.clone(),
+            })
+        })
+        .collect();
+
+    languages.sort_by_key(|language| language.name.to_lowercase());
+    languages
+}
+
+/// Used when neither the visitor nor the configuration picks a usable
+/// theme. Always part of the default theme set.
+pub const DEFAULT_THEME: &str = "Solarized (dark)";
+
+/// URL and cookie friendly form of a theme name, e.g. `Solarized (dark)`
+/// becomes `solarized-dark`.
+pub fn theme_id(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// How highlighted code is coloured.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ThemeChoice {
+    /// Inline styles from a single theme, by its name in the theme set.
+    Named(&'static str),
+    /// CSS classes, with a light and a dark stylesheet picked by the
+    /// browser through `prefers-color-scheme`.
+    Auto {
+        light: &'static str,
+        dark: &'static str,
+    },
+}
+
+impl ThemeChoice {
+    /// Looks up a theme by id or by its exact name. `auto` selects the
+    /// given light and dark themes.
+    pub fn parse(value: &str, light: &str, dark: &str) -> Option<Self> {
+        if value == "auto" {
+            return Some(ThemeChoice::Auto {
+                light: find_theme(light)?,
+                dark: find_theme(dark)?,
+            });
+        }
+
+        find_theme(value).map(ThemeChoice::Named)
+    }
+
+    /// What to remember in the theme cookie.
+    pub fn id(&self) -> String {
+        match self {
+            ThemeChoice::Named(name) => theme_id(name),
+            ThemeChoice::Auto { .. } => "auto".to_string(),
+        }
+    }
+}
+
+fn find_theme(value: &str) -> Option<&'static str> {
+    theme_set()
+        .themes
+        .keys()
+        .find(|name| name.as_str() == value || theme_id(name) == value)
+        .map(String::as_str)
+}
+
+fn is_dark(theme: &Theme) -> bool {
+    theme.settings.background.map_or(false, |color| {
+        (u32::from(color.r) * 299 + u32::from(color.g) * 587 + u32::from(color.b) * 114) / 1000
+            < 128
+    })
+}
+
+pub fn themes() -> Vec<types::ThemeInfo> {
+    theme_set()
+        .themes
+        .iter()
+        .map(|(name, theme)| types::ThemeInfo {
+            id: theme_id(name),
+            name: name.clone(),
+            dark: is_dark(theme),
+        })
+        .collect()
+}
+
+/// Line numbers picked with `?hl=`, e.g. `42`, `10-20` or `L10-L20,42`.
+#[derive(Debug, Default, PartialEq)]
+pub struct LineRanges(Vec<(usize, usize)>);
+
+impl LineRanges {
+    /// Unparseable parts are skipped, a bad link shouldn't break the page.
+    pub fn parse(value: &str) -> Self {
+        let line = |part: &str| part.trim().trim_start_matches('L').parse::<usize>().ok();
+
+        LineRanges(
+            value
+                .split(',')
+                .filter_map(|part| match part.split_once('-') {
+                    Some((from, to)) => Some((line(from)?, line(to)?)),
+                    None => line(part).map(|n| (n, n)),
+                })
+                .map(|(from, to)| (from.min(to), from.max(to)))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, line: usize) -> bool {
+        self.0
+            .iter()
+            .any(|(from, to)| (*from..=*to).contains(&line))
+    }
+}
+
+/// Marks `#L10-L20` fragments the same way `?hl=` does, and turns a
+/// shift-click on a line number into a range.
+const LINE_SCRIPT: &str = r##"<script>
+(function () {
+  function mark() {
+    var m = /^#L(\d+)(?:-L?(\d+))?$/.exec(location.hash);
+    if (!m) return;
+    var from = +m[1], to = +(m[2] || m[1]);
+    document.querySelectorAll(".line.hl").forEach(function (el) { el.classList.remove("hl"); });
+    for (var n = Math.min(from, to); n <= Math.max(from, to); n++) {
+      var line = document.getElementById("L" + n);
+      if (line) line.classList.add("hl");
+    }
+  }
+  document.addEventListener("click", function (event) {
+    var link = event.target.closest("a.ln");
+    var start = /^#L(\d+)/.exec(location.hash);
+    if (!link || !event.shiftKey || !start) return;
+    event.preventDefault();
+    location.hash = "#L" + start[1] + "-" + link.hash.slice(1);
+  });
+  window.addEventListener("hashchange", mark);
+  mark();
+})();
+</script>"##;
+
+fn line_css(line_count: usize) -> String {
+    format!(
+        "pre .line {{ display: block; }}\n\
+         pre .line.hl, pre .line:target {{ background-color: rgba(255, 215, 0, 0.25); }}\n\
+         pre .ln {{ display: inline-block; min-width: {}ch; margin-right: 2ch; \
+         text-align: right; color: inherit; opacity: 0.5; text-decoration: none; \
+         user-select: none; }}\n",
+        line_count.to_string().len()
+    )
+}
+
+/// Highlighted code and the stylesheet it needs.
+pub struct Highlighted {
+    pub html: String,
+    pub css: String,
+}
+
+/// Renders every line on its own, wrapped in a `#L<n>` anchor with a
+/// clickable line number, so lines can be linked and marked.
+pub fn highlight(
+    code: &str,
+    syntax: &SyntaxReference,
+    choice: &ThemeChoice,
+    marked: &LineRanges,
+) -> Result<Highlighted> {
+    let (open, lines, theme_css) = match choice {
+        ThemeChoice::Named(name) => {
+            let theme = &theme_set().themes[*name];
+            let (open, background) = start_highlighted_html_snippet(theme);
+
+            (open, inline_lines(code, syntax, theme, background)?, None)
+        }
+        ThemeChoice::Auto { light, dark } => (
+            "<pre class=\"code\">".to_string(),
+            classed_lines(code, syntax)?,
+            Some(auto_css(light, dark)?),
+        ),
+    };
+
+    let mut html = open;
+    for (index, line) in lines.iter().enumerate() {
+        let n = index + 1;
+        let class = if marked.contains(n) {
+            "line hl"
+        } else {
+            "line"
+        };
+
+        // The line wrappers are blocks, so the newlines themselves would
+        // only add blank lines.
+        html.push_str(&format!(
+            "<span class=\"{class}\" id=\"L{n}\"><a class=\"ln\" href=\"?hl={n}#L{n}\">{n}</a>{}</span>",
+            line.replace('\n', "")
+        ));
+    }
+    html.push_str("</pre>\n");
+    html.push_str(LINE_SCRIPT);
+
+    let mut css = line_css(lines.len());
+    if let Some(theme_css) = theme_css {
+        css.push_str(&theme_css);
+    }
+
+    Ok(Highlighted { html, css })
+}
+
+/// A plain highlighted `<pre>` block, without line numbers or anchors, for
+/// code embedded in other documents. `Auto` blocks rely on the stylesheet
+/// from [`theme_css`].
+pub fn highlight_block(
+    code: &str,
+    syntax: &SyntaxReference,
+    choice: &ThemeChoice,
+) -> Result<String> {
+    let (open, lines) = match choice {
+        ThemeChoice::Named(name) => {
+            let theme = &theme_set().themes[*name];
+            let (open, background) = start_highlighted_html_snippet(theme);
+
+            (open, inline_lines(code, syntax, theme, background)?)
+        }
+        ThemeChoice::Auto { .. } => (
+            "<pre class=\"code\">".to_string(),
+            classed_lines(code, syntax)?,
+        ),
+    };
+
+    Ok(format!("{}{}</pre>\n", open, lines.concat()))
+}
+
+/// The stylesheet `Auto` output needs. Inline themes need none.
+pub fn theme_css(choice: &ThemeChoice) -> Result<Option<String>> {
+    match choice {
+        ThemeChoice::Named(_) => Ok(None),
+        ThemeChoice::Auto { light, dark } => auto_css(light, dark).map(Some),
+    }
+}
+
+fn inline_lines(
+    code: &str,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    background: Color,
+) -> Result<Vec<String>> {
+    let mut highlighter = HighlightLines::new(syntax, theme);
+
+    LinesWithEndings::from(code)
+        .map(|line| {
+            let regions = highlighter.highlight_line(line, syntax_set())?;
+            Ok(styled_line_to_highlighted_html(
+                &regions,
+                IncludeBackground::IfDifferent(background),
+            )?)
+        })
+        .collect()
+}
+
+/// Scopes can stay open across lines, so each line reopens the spans of
+/// the scopes it starts in and closes whatever is still open at its end.
+fn classed_lines(code: &str, syntax: &SyntaxReference) -> Result<Vec<String>> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+
+    LinesWithEndings::from(code)
+        .map(|line| {
+            let mut html: String = stack
+                .as_slice()
+                .iter()
+                .map(|scope| {
+                    format!(
+                        "<span class=\"{}\">",
+                        scope.build_string().replace('.', " ")
+                    )
+                })
+                .collect();
+
+            let ops = state.parse_line(line, syntax_set())?;
+            let (spans, _) =
+                line_tokens_to_classed_spans(line, &ops, ClassStyle::Spaced, &mut stack)?;
+
+            html.push_str(&spans);
+            html.push_str(&"</span>".repeat(stack.len()));
+            Ok(html)
+        })
+        .collect()
+}
+
+/// Colour depth of terminal output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colors {
+    TrueColor,
+    /// The xterm 256-colour palette, for terminals without 24-bit support.
+    Palette256,
+}
+
+/// Highlights `code` with ANSI escapes for a terminal. Only foreground
+/// colours are set, the terminal keeps its own background.
+pub fn highlight_ansi(
+    code: &str,
+    syntax: &SyntaxReference,
+    choice: &ThemeChoice,
+    colors: Colors,
+) -> Result<String> {
+    // Terminals are mostly dark, so `auto` means the dark theme here.
+    let name = match choice {
+        ThemeChoice::Named(name) => name,
+        ThemeChoice::Auto { dark, .. } => dark,
+    };
+    let mut highlighter = HighlightLines::new(syntax, &theme_set().themes[*name]);
+    let mut output = String::new();
+
+    for line in LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(line, syntax_set())?;
+
+        match colors {
+            Colors::TrueColor => output.push_str(&as_24_bit_terminal_escaped(&regions, false)),
+            Colors::Palette256 => {
+                for (style, text) in regions {
+                    output.push_str(&format!("\x1b[38;5;{}m{}", ansi256(style.foreground), text));
+                }
+            }
+        }
+    }
+
+    output.push_str("\x1b[0m");
+    Ok(output)
+}
+
+/// Nearest xterm palette entry, from either the 6x6x6 colour cube or the
+/// grayscale ramp.
+fn ansi256(color: Color) -> u8 {
+    const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    let cube_index = |value: u8| match value {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (value - 35) / 40,
+    };
+    let distance = |r: u8, g: u8, b: u8| {
+        let d = |x: u8, y: u8| (i32::from(x) - i32::from(y)).pow(2);
+        d(r, color.r) + d(g, color.g) + d(b, color.b)
+    };
+
+    let (r, g, b) = (
+        cube_index(color.r),
+        cube_index(color.g),
+        cube_index(color.b),
+    );
+    let cube = 16 + 36 * r + 6 * g + b;
+    let cube_distance = distance(CUBE[r as usize], CUBE[g as usize], CUBE[b as usize]);
+
+    let average = ((u16::from(color.r) + u16::from(color.g) + u16::from(color.b)) / 3) as u8;
+    let gray = if average > 238 {
+        23
+    } else {
+        average.saturating_sub(3) / 10
+    };
+    let level = 8 + 10 * gray;
+    let gray_distance = distance(level, level, level);
+
+    if gray_distance < cube_distance {
+        232 + gray
+    } else {
+        cube
+    }
+}
+
+/// Both stylesheets sit behind mutually exclusive media queries, so a rule
+/// that only one of the themes defines can't leak into the other.
+fn auto_css(light: &str, dark: &str) -> Result<String> {
+    let css = |name: &str| {
+        css_for_theme_with_class_style(&theme_set().themes[name], ClassStyle::Spaced)
+            .map_err(|err| anyhow::anyhow!("couldn't generate theme css: {}", err))
+    };
+
+    Ok(format!(
+        "@media not all and (prefers-color-scheme: dark) {{\n{}}}\n\
+         @media (prefers-color-scheme: dark) {{\n{}}}\n",
+        css(light)?,
+        css(dark)?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_load_once_with_the_defaults() {
+        assert!(syntax_set().find_syntax_by_extension("rs").is_some());
+        assert!(theme_set().themes.contains_key("Solarized (dark)"));
+        assert!(std::ptr::eq(syntax_set(), syntax_set()));
+    }
+
+    #[test]
+    fn themes_are_found_by_id_or_name() {
+        let dark = Some(ThemeChoice::Named("Solarized (dark)"));
+
+        assert_eq!(theme_id("Solarized (dark)"), "solarized-dark");
+        assert_eq!(ThemeChoice::parse("solarized-dark", "", ""), dark);
+        assert_eq!(ThemeChoice::parse("Solarized (dark)", "", ""), dark);
+        assert_eq!(ThemeChoice::parse("nope", "", ""), None);
+    }
+
+    #[test]
+    fn languages_round_trip_through_their_id() {
+        let languages = languages();
+
+        let rust = languages.iter().find(|lang| lang.name == "Rust").unwrap();
+        assert_eq!(rust.id, "rs");
+
+        let erb = languages
+            .iter()
+            .find(|lang| lang.extensions.iter().any(|ext| ext == "html.erb"))
+            .unwrap();
+        assert_eq!(erb.name, "HTML (Rails)");
+
+        for language in &languages {
+            let syntax = syntax_set().find_syntax_by_extension(&language.id).unwrap();
+            assert_eq!(syntax.name, language.name);
+        }
+    }
+
+    #[test]
+    fn auto_themes_emit_classes_and_css() {
+        let choice = ThemeChoice::parse("auto", "inspiredgithub", "solarized-dark").unwrap();
+        let syntax = syntax_set().find_syntax_by_extension("rs").unwrap();
+        let highlighted =
+            highlight("fn main() {}\n", syntax, &choice, &LineRanges::default()).unwrap();
+
+        assert!(highlighted.html.starts_with("<pre class=\"code\">"));
+        assert!(!highlighted.html.contains("style="));
+        assert!(highlighted
+            .css
+            .contains("@media (prefers-color-scheme: dark)"));
+    }
+
+    #[test]
+    fn line_ranges_accept_numbers_and_anchors() {
+        let ranges = LineRanges::parse("L20-L10,42,x-3");
+
+        assert_eq!(ranges, LineRanges(vec![(10, 20), (42, 42)]));
+        assert!(ranges.contains(15));
+        assert!(!ranges.contains(21));
+    }
+
+    #[test]
+    fn every_line_gets_an_anchor() {
+        let syntax = syntax_set().find_syntax_by_extension("rs").unwrap();
+        let code = "/* a\n b */\nfn main() {}\n";
+        let marked = LineRanges::parse("2");
+
+        for choice in [
+            ThemeChoice::Named(DEFAULT_THEME),
+            ThemeChoice::parse("auto", "inspiredgithub", "solarized-dark").unwrap(),
+        ] {
+            let html = highlight(code, syntax, &choice, &marked).unwrap().html;
+
+            assert!(html.contains("<span class=\"line\" id=\"L1\">"));
+            assert!(html.contains("<span class=\"line hl\" id=\"L2\">"));
+            assert!(html.contains("href=\"?hl=3#L3\""));
+            assert!(!html.contains("id=\"L4\""));
+            assert_eq!(
+                html.matches("<span").count(),
+                html.matches("</span>").count()
+            );
+        }
+    }
+
+    #[test]
+    fn ansi_output_has_true_color_or_palette_escapes() {
+        let syntax = syntax_set().find_syntax_by_extension("rs").unwrap();
+        let choice = ThemeChoice::Named(DEFAULT_THEME);
+
+        let true_color = highlight_ansi("fn main() {}\n", syntax, &choice, Colors::TrueColor);
+        let palette = highlight_ansi("fn main() {}\n", syntax, &choice, Colors::Palette256);
+
+        assert!(true_color.unwrap().contains("\x1b[38;2;"));
+        let palette = palette.unwrap();
+        assert!(palette.contains("\x1b[38;5;"));
+        assert!(palette.ends_with("\x1b[0m"));
+    }
+
+    #[test]
+    fn palette_matches_cube_and_grays() {
+        let rgb = |r, g, b| Color { r, g, b, a: 0xFF };
+
+        assert_eq!(ansi256(rgb(255, 0, 0)), 196);
+        assert_eq!(ansi256(rgb(0, 0, 0)), 16);
+        assert_eq!(ansi256(rgb(128, 128, 128)), 244);
+    }
+}
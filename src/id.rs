@@ -0,0 +1,273 @@
+use anyhow::{bail, Result};
+use rand::{seq::SliceRandom, Rng};
+use std::future::Future;
+use std::str::FromStr;
+
+use crate::config::Config;
+
+const ALPHANUMERIC: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// No 0/O, 1/l/I, so ids survive being read aloud or copied from a screenshot.
+const UNAMBIGUOUS: &str = "23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz";
+
+const WORDS: &[&str] = &[
+    "amber", "anchor", "apple", "arrow", "aspen", "atlas", "autumn", "badge", "bamboo", "banjo",
+    "basil", "beacon", "birch", "bison", "blaze", "bloom", "bonsai", "breeze", "brook", "cabin",
+    "cactus", "candle", "canyon", "cedar", "cherry", "cider", "cinder", "clover", "cobalt",
+    "comet", "copper", "coral", "cosmos", "cotton", "crane", "crystal", "dahlia", "delta",
+    "desert", "dune", "eagle", "ember", "falcon", "fern", "fjord", "flint", "forest", "fossil",
+    "galaxy", "garnet", "geyser", "ginger", "glacier", "granite", "harbor", "hazel", "heron",
+    "hollow", "honey", "indigo", "island", "ivory", "jasper", "juniper", "kayak", "kelp", "kettle",
+    "lagoon", "lantern", "lemon", "lilac", "linen", "lotus", "lunar", "maple", "marble", "meadow",
+    "melon", "mesa", "mango", "mint", "monsoon", "mosaic", "nectar", "nimbus", "nova", "oasis",
+    "ocean", "olive", "onyx", "orbit", "orchid", "otter", "panda", "pebble", "pepper", "pine",
+    "pixel", "plume", "polar", "poppy", "prairie", "quartz", "quill", "raven", "reef", "ripple",
+    "river", "robin", "saffron", "sage", "salmon", "sierra", "silver", "sparrow", "spruce",
+    "summit", "tango", "thistle", "timber", "topaz", "tulip", "tundra", "velvet", "violet",
+    "walnut", "willow", "zephyr",
+];
+
+const DEFAULT_MAX_ATTEMPTS: usize = 8;
+
+/// Whether `id` could name a paste. Generated ids and slugs are made of
+/// ASCII letters, digits and dashes, while the store's internal keys
+/// (chunks, tombstones, slug reservations) all contain a colon, so they
+/// can't be read or deleted as pastes.
+pub fn is_paste_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IdScheme {
+    /// Mixed-case alphanumeric ids, the original paste id format.
+    #[default]
+    Random,
+    /// Alphanumeric ids without look-alike characters.
+    Unambiguous,
+    /// Dash-separated dictionary words, e.g. `amber-falcon-river`.
+    Words,
+}
+
+impl FromStr for IdScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "random" | "alphanumeric" => Ok(IdScheme::Random),
+            "unambiguous" => Ok(IdScheme::Unambiguous),
+            "words" | "pronounceable" => Ok(IdScheme::Words),
+            other => bail!("unknown id scheme: {}", other),
+        }
+    }
+}
+
+pub struct IdGenerator {
+    scheme: IdScheme,
+    /// Number of characters, or number of words for [`IdScheme::Words`].
+    length: usize,
+    alphabet: Vec<char>,
+    max_attempts: usize,
+}
+
+impl IdGenerator {
+    pub fn new(scheme: IdScheme) -> Self {
+        let (length, alphabet) = match scheme {
+            IdScheme::Random => (7, ALPHANUMERIC),
+            IdScheme::Unambiguous => (8, UNAMBIGUOUS),
+            IdScheme::Words => (3, ""),
+        };
+
+        IdGenerator {
+            scheme,
+            length,
+            alphabet: alphabet.chars().collect(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mut generator = IdGenerator::new(config.id_scheme);
+
+        if let Some(length) = config.id_length.filter(|length| *length > 0) {
+            generator = generator.with_length(length);
+        }
+
+        if let Some(alphabet) = &config.id_alphabet {
+            generator = generator.with_alphabet(alphabet);
+        }
+
+        generator
+    }
+
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// Overrides the character set. Ignored by the word scheme.
+    pub fn with_alphabet(mut self, alphabet: &str) -> Self {
+        let mut chars: Vec<char> = alphabet
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        chars.sort_unstable();
+        chars.dedup();
+
+        if !chars.is_empty() {
+            self.alphabet = chars;
+        }
+
+        self
+    }
+
+    pub fn candidate<R: Rng>(&self, rng: &mut R) -> String {
+        match self.scheme {
+            IdScheme::Words => (0..self.length)
+                .map(|_| *WORDS.choose(rng).unwrap())
+                .collect::<Vec<_>>()
+                .join("-"),
+            IdScheme::Random | IdScheme::Unambiguous => (0..self.length)
+                .map(|_| *self.alphabet.choose(rng).unwrap())
+                .collect(),
+        }
+    }
+
+    /// Draws candidates until `exists` reports one as free, so a new paste
+    /// never overwrites an existing key.
+    pub async fn generate<F, Fut>(&self, exists: F) -> Result<String>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<bool>>,
+    {
+        self.generate_with(&mut rand::thread_rng(), exists).await
+    }
+
+    pub async fn generate_with<R, F, Fut>(&self, rng: &mut R, mut exists: F) -> Result<String>
+    where
+        R: Rng,
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<bool>>,
+    {
+        for _ in 0..self.max_attempts {
+            let id = self.candidate(rng);
+
+            if !exists(id.clone()).await? {
+                return Ok(id);
+            }
+        }
+
+        bail!(
+            "couldn't find a free id after {} attempts",
+            self.max_attempts
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    fn fake_store(keys: &[&str]) -> RefCell<HashSet<String>> {
+        RefCell::new(keys.iter().map(|key| key.to_string()).collect())
+    }
+
+    #[test]
+    fn retries_past_colliding_ids() {
+        let store = fake_store(&["a"]);
+        let generator = IdGenerator {
+            max_attempts: 64,
+            ..IdGenerator::new(IdScheme::Random)
+                .with_alphabet("ab")
+                .with_length(1)
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let lookups = RefCell::new(0);
+
+        let id = block_on(generator.generate_with(&mut rng, |id| {
+            *lookups.borrow_mut() += 1;
+            let taken = store.borrow().contains(&id);
+            async move { Ok(taken) }
+        }))
+        .unwrap();
+
+        assert_eq!(id, "b");
+        assert!(*lookups.borrow() >= 1);
+    }
+
+    #[test]
+    fn gives_up_when_every_id_is_taken() {
+        let store = fake_store(&["a", "b"]);
+        let generator = IdGenerator {
+            max_attempts: 5,
+            ..IdGenerator::new(IdScheme::Random)
+                .with_alphabet("ab")
+                .with_length(1)
+        };
+        let lookups = RefCell::new(0);
+
+        let result = block_on(generator.generate(|id| {
+            *lookups.borrow_mut() += 1;
+            let taken = store.borrow().contains(&id);
+            async move { Ok(taken) }
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(*lookups.borrow(), 5);
+    }
+
+    #[test]
+    fn propagates_store_errors() {
+        let generator = IdGenerator::new(IdScheme::Random);
+
+        let result = block_on(generator.generate(|_| async { bail!("kv unavailable") }));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unambiguous_ids_avoid_look_alikes() {
+        let generator = IdGenerator::new(IdScheme::Unambiguous).with_length(500);
+        let id = generator.candidate(&mut StdRng::seed_from_u64(1));
+
+        assert_eq!(id.len(), 500);
+        assert!(!id.contains(['0', 'O', '1', 'l', 'I']));
+    }
+
+    #[test]
+    fn word_ids_join_dictionary_words() {
+        let generator = IdGenerator::new(IdScheme::Words).with_length(4);
+        let id = generator.candidate(&mut StdRng::seed_from_u64(3));
+        let words: Vec<&str> = id.split('-').collect();
+
+        assert_eq!(words.len(), 4);
+        assert!(words.iter().all(|word| WORDS.contains(word)));
+    }
+
+    #[test]
+    fn internal_keys_are_not_paste_ids() {
+        assert!(is_paste_id("aB3xY9z"));
+        assert!(is_paste_id("amber-falcon-river"));
+
+        assert!(!is_paste_id(""));
+        assert!(!is_paste_id("chunk:aB3xY9z:0"));
+        assert!(!is_paste_id("tombstone:aB3xY9z"));
+        assert!(!is_paste_id("slug:deploy-checklist"));
+    }
+
+    #[test]
+    fn scheme_and_length_come_from_config() {
+        let config = Config::from_lookup(|name| match name {
+            "PASTE_ID_SCHEME" => Some("unambiguous".to_string()),
+            "PASTE_ID_LENGTH" => Some("12".to_string()),
+            _ => None,
+        });
+        let id = IdGenerator::from_config(&config).candidate(&mut StdRng::seed_from_u64(9));
+
+        assert_eq!(config.id_scheme, IdScheme::Unambiguous);
+        assert_eq!(id.len(), 12);
+        assert!(id.chars().all(|c| UNAMBIGUOUS.contains(c)));
+    }
+}
@@ -1,8 +1,36 @@
 use worker::*;
 
-mod handlers;
-mod types;
+/// `console_error!` on Workers, stderr elsewhere (e.g. under `cargo test`,
+/// where the JS console isn't available).
+macro_rules! log_error {
+    ($($t:tt)*) => {{
+        #[cfg(target_arch = "wasm32")]
+        worker::console_error!($($t)*);
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!($($t)*);
+    }};
+}
+
+mod chunks;
+pub mod config;
+mod detect;
+pub mod error;
+mod expiry;
+pub mod handlers;
+mod highlight;
+mod id;
+mod markdown;
+mod paste_path;
+pub mod routes;
+#[cfg(feature = "server")]
+pub mod server;
+mod slug;
+pub mod store;
+mod templates;
+mod token;
+pub mod types;
 mod utils;
+pub mod web;
 
 fn log_request(req: &Request) {
     console_log!(
@@ -15,34 +43,40 @@ fn log_request(req: &Request) {
 }
 
 #[event(fetch)]
-pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
+pub async fn main(mut req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
+    utils::set_panic_hook();
+
     log_request(&req);
 
-    utils::set_panic_hook();
+    let config = config::Config::from_env(&env);
+    let store = std::rc::Rc::new(store::KvPasteStore::new(env.kv("code_paste")?));
+    let app = handlers::App {
+        store: &*store,
+        config: &config,
+        now: Date::now().as_millis() / 1000,
+    };
+
+    let path = req.path();
+    let reply = match routes::find(req.method().as_ref(), &path) {
+        Some((endpoint, params)) => {
+            let incoming = web::incoming(
+                &mut req,
+                params,
+                endpoint.reads_body(),
+                config.max_request_size,
+            )
+            .await?;
+            let headers = web::Incoming {
+                headers: incoming.headers.clone(),
+                ..Default::default()
+            };
+            handlers::guarded(&app, &headers, endpoint.call(&app, incoming)).await
+        }
+        None => {
+            let incoming = web::incoming(&mut req, Default::default(), false, 0).await?;
+            app.fail(&incoming, error::PasteError::NotFound)
+        }
+    };
 
-    let router = Router::new();
-
-    router
-        .get("/", |_, _| {
-            let mut headers: http::HeaderMap = Headers::new().into();
-            headers.append("Cache-Control", "max-age=2629746".parse().unwrap());
-            headers.append("Content-Type", "text/html".parse().unwrap());
-
-            let rendered = utils::get_web_template();
-
-            return Response::ok(rendered).map(|res| res.with_headers(headers.into()));
-        })
-        .post_async("/", |req, ctx| async move {
-            handlers::create_paste(req, ctx).await
-        })
-        .get_async(
-            "/:id",
-            |_, ctx| async move { handlers::get_paste(ctx).await },
-        )
-        .delete_async(
-            "/:id",
-            |_, ctx| async move { handlers::delete_paste(ctx).await },
-        )
-        .run(req, env)
-        .await
+    web::into_response(reply, store)
 }
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+
+use crate::highlight::{highlight_block, resolve_language_override, syntax_set, ThemeChoice};
+
+/// Link and image targets allowed through. Anything else, `javascript:`
+/// in particular, is replaced by `#`.
+const SAFE_SCHEMES: &[&str] = &["http:", "https:", "mailto:"];
+
+fn sanitize_url(url: CowStr<'_>) -> CowStr<'_> {
+    let lowered = url.trim().to_ascii_lowercase();
+    let has_scheme = lowered
+        .split(['/', '?', '#'])
+        .next()
+        .map_or(false, |head| head.contains(':'));
+
+    match !has_scheme
+        || SAFE_SCHEMES
+            .iter()
+            .any(|scheme| lowered.starts_with(scheme))
+    {
+        true => url,
+        false => CowStr::Borrowed("#"),
+    }
+}
+
+/// Renders CommonMark with the GFM extensions (tables, strikethrough, task
+/// lists, footnotes) into HTML that is safe to serve from this origin: raw
+/// HTML in the source is shown as text and unsafe URLs are dropped. Fenced
+/// code blocks are highlighted with `theme`.
+pub fn render(source: &str, theme: &ThemeChoice) -> Result<String> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut events = Vec::new();
+    // Language and text of the fenced block being collected, if any.
+    let mut fence: Option<(String, String)> = None;
+
+    for event in Parser::new_ext(source, options) {
+        if let Some((lang, code)) = &mut fence {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(Tag::CodeBlock(_)) => {
+                    let syntax = resolve_language_override(lang)
+                        .and_then(|lang| syntax_set().find_syntax_by_extension(&lang))
+                        .unwrap_or_else(|| syntax_set().find_syntax_plain_text());
+
+                    events.push(Event::Html(highlight_block(code, syntax, theme)?.into()));
+                    fence = None;
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let event = match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
+                let lang = lang.split_whitespace().next().unwrap_or_default();
+                fence = Some((lang.to_string(), String::new()));
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)) => {
+                fence = Some((String::new(), String::new()));
+                continue;
+            }
+            Event::Html(raw) => Event::Text(raw),
+            Event::Start(Tag::Link(kind, url, title)) => {
+                Event::Start(Tag::Link(kind, sanitize_url(url), title))
+            }
+            Event::End(Tag::Link(kind, url, title)) => {
+                Event::End(Tag::Link(kind, sanitize_url(url), title))
+            }
+            Event::Start(Tag::Image(kind, url, title)) => {
+                Event::Start(Tag::Image(kind, sanitize_url(url), title))
+            }
+            Event::End(Tag::Image(kind, url, title)) => {
+                Event::End(Tag::Image(kind, sanitize_url(url), title))
+            }
+            event => event,
+        };
+
+        events.push(event);
+    }
+
+    let mut output = String::new();
+    html::push_html(&mut output, events.into_iter());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::DEFAULT_THEME;
+
+    fn render_default(source: &str) -> String {
+        render(source, &ThemeChoice::Named(DEFAULT_THEME)).unwrap()
+    }
+
+    #[test]
+    fn renders_gfm() {
+        let html =
+            render_default("# Runbook\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n- [x] ~~done~~\n");
+
+        assert!(html.contains("<h1>Runbook</h1>"));
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<del>done</del>"));
+        assert!(html.contains("type=\"checkbox\""));
+    }
+
+    #[test]
+    fn raw_html_and_script_urls_are_neutralised() {
+        let html = render_default(
+            "<script>alert(1)</script>\n\n[x](javascript:alert(1)) [y](https://example.com) [z](/abc)\n",
+        );
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("href=\"#\""));
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(html.contains("href=\"/abc\""));
+    }
+
+    #[test]
+    fn fenced_code_is_highlighted() {
+        let html = render_default("```rust\nfn main() {}\n```\n");
+
+        assert!(html.contains("<pre style="));
+        assert!(html.contains("<span style="));
+        assert!(!html.contains("<code class=\"language-rust\">"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::highlight;
+
+/// The parts of a paste URL: `/:id`, `/:id.ext`, `/:id.ext.ext` or
+/// `/:id/:filename`. Ids and slugs never contain dots, so everything after
+/// the first one is the extension.
+#[derive(Debug, PartialEq)]
+pub struct PastePath<'a> {
+    pub id: &'a str,
+    pub ext: Option<&'a str>,
+    pub filename: Option<&'a str>,
+}
+
+impl<'a> PastePath<'a> {
+    pub fn parse(id: &'a str, filename: Option<&'a str>) -> Self {
+        let (id, ext) = match id.split_once('.') {
+            Some((id, ext)) => (id, Some(ext).filter(|ext| !ext.is_empty())),
+            None => (id, None),
+        };
+
+        PastePath {
+            id,
+            ext,
+            filename: filename.filter(|name| !name.is_empty()),
+        }
+    }
+
+    /// The path without its leading slash, for links back to it.
+    pub fn to_path(&self) -> String {
+        match (self.ext, self.filename) {
+            (_, Some(filename)) => format!("{}/{}", self.id, filename),
+            (Some(ext), None) => format!("{}.{}", self.id, ext),
+            (None, None) => self.id.to_string(),
+        }
+    }
+
+    /// The last extension in the path, e.g. `json` for `/abc/data.json`.
+    pub fn extension(&self) -> Option<&'a str> {
+        match self.filename {
+            Some(filename) => filename.rsplit_once('.').map(|(_, ext)| ext),
+            None => self.ext.and_then(|ext| ext.rsplit('.').next()),
+        }
+    }
+
+    /// The language the path asks for, as a token for
+    /// [`highlight::resolve_language`]. The filename wins over the
+    /// extension; unknown ones yield `None`.
+    pub fn language(&self) -> Option<String> {
+        self.filename
+            .or(self.ext)
+            .and_then(highlight::resolve_language)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_ids_have_no_language() {
+        let path = PastePath::parse("abc", None);
+
+        assert_eq!(path.id, "abc");
+        assert_eq!(path.language(), None);
+        assert_eq!(path.to_path(), "abc");
+    }
+
+    #[test]
+    fn single_extensions_select_the_syntax() {
+        let path = PastePath::parse("abc.rs", None);
+
+        assert_eq!(path.ext, Some("rs"));
+        assert_eq!(path.language().as_deref(), Some("rs"));
+    }
+
+    #[test]
+    fn multi_dot_extensions_fall_back_to_shorter_suffixes() {
+        let erb = PastePath::parse("abc.css.erb", None);
+        assert_eq!(erb.id, "abc");
+        assert_eq!(erb.ext, Some("css.erb"));
+        assert_eq!(erb.extension(), Some("erb"));
+        assert!(erb.language().is_some());
+
+        let rack = PastePath::parse("abc.config.ru", None);
+        assert_eq!(rack.language().as_deref(), Some("config.ru"));
+        assert_eq!(rack.to_path(), "abc.config.ru");
+    }
+
+    #[test]
+    fn filenames_select_the_syntax_by_name() {
+        let make = PastePath::parse("abc", Some("Makefile"));
+        assert_eq!(make.language().as_deref(), Some("Makefile"));
+        assert_eq!(make.to_path(), "abc/Makefile");
+
+        let json = PastePath::parse("abc", Some("data.json"));
+        assert_eq!(json.language().as_deref(), Some("json"));
+        assert_eq!(json.extension(), Some("json"));
+    }
+
+    #[test]
+    fn unknown_extensions_are_ignored() {
+        assert_eq!(PastePath::parse("abc.nope", None).language(), None);
+        assert_eq!(PastePath::parse("abc.", None).ext, None);
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use crate::{
+    handlers::{self, App},
+    web::{Incoming, Reply},
+};
+
+/// The handlers requests are routed to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endpoint {
+    Index,
+    CreatePaste,
+    Discovery,
+    CreatePasteJson,
+    ListThemes,
+    ListLanguages,
+    ReserveSlug,
+    RawPaste,
+    RevealRawPaste,
+    GetPaste,
+    RevealPaste,
+    DeletePaste,
+    PutPaste,
+}
+
+impl Endpoint {
+    /// Whether the body is read, and forms parsed, before the handler runs.
+    pub fn reads_body(self) -> bool {
+        matches!(
+            self,
+            Endpoint::CreatePaste | Endpoint::CreatePasteJson | Endpoint::PutPaste
+        )
+    }
+
+    /// Runs the endpoint's handler. Handlers that read the body take the
+    /// request by value, so uploads can be moved into the paste.
+    pub async fn call(self, app: &App<'_>, req: Incoming) -> Reply {
+        match self {
+            Endpoint::Index => handlers::index(app, &req).await,
+            Endpoint::CreatePaste => handlers::create_paste(app, req).await,
+            Endpoint::Discovery => handlers::discovery(app, &req).await,
+            Endpoint::CreatePasteJson => handlers::create_paste_json(app, req).await,
+            Endpoint::ListThemes => handlers::list_themes(app, &req).await,
+            Endpoint::ListLanguages => handlers::list_languages(app, &req).await,
+            Endpoint::ReserveSlug => handlers::reserve_slug(app, &req).await,
+            Endpoint::RawPaste => handlers::raw_paste(app, &req).await,
+            Endpoint::RevealRawPaste => handlers::reveal_raw_paste(app, &req).await,
+            Endpoint::GetPaste => handlers::get_paste(app, &req).await,
+            Endpoint::RevealPaste => handlers::reveal_paste(app, &req).await,
+            Endpoint::DeletePaste => handlers::delete_paste(app, &req).await,
+            Endpoint::PutPaste => handlers::put_paste(app, req).await,
+        }
+    }
+}
+
+/// Method, path pattern and endpoint of every route. `:name` matches one
+/// non-empty path segment. The first match wins, so static routes come
+/// before the parameters they would otherwise fall into.
+pub const ROUTES: &[(&str, &str, Endpoint)] = &[
+    ("GET", "/", Endpoint::Index),
+    ("POST", "/", Endpoint::CreatePaste),
+    ("GET", "/api", Endpoint::Discovery),
+    ("POST", "/api/pastes", Endpoint::CreatePasteJson),
+    ("GET", "/api/themes", Endpoint::ListThemes),
+    ("GET", "/api/languages", Endpoint::ListLanguages),
+    ("POST", "/api/slugs/:slug", Endpoint::ReserveSlug),
+    ("GET", "/raw/:id", Endpoint::RawPaste),
+    ("GET", "/raw/:id/:filename", Endpoint::RawPaste),
+    ("POST", "/raw/:id", Endpoint::RevealRawPaste),
+    ("POST", "/raw/:id/:filename", Endpoint::RevealRawPaste),
+    ("GET", "/:id", Endpoint::GetPaste),
+    ("GET", "/:id/:filename", Endpoint::GetPaste),
+    ("POST", "/:id", Endpoint::RevealPaste),
+    ("POST", "/:id/:filename", Endpoint::RevealPaste),
+    ("DELETE", "/:id", Endpoint::DeletePaste),
+    ("PUT", "/:filename", Endpoint::PutPaste),
+];
+
+/// Finds the endpoint for `method` and `path`, along with the percent-decoded
+/// values of the route's parameters.
+pub fn find(method: &str, path: &str) -> Option<(Endpoint, HashMap<String, String>)> {
+    ROUTES
+        .iter()
+        .filter(|(route_method, _, _)| *route_method == method)
+        .find_map(|(_, pattern, endpoint)| Some((*endpoint, match_pattern(pattern, path)?)))
+}
+
+fn match_pattern(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut segments = path.split('/');
+
+    for part in pattern.split('/') {
+        let segment = segments.next()?;
+
+        match part.strip_prefix(':') {
+            Some(_) if segment.is_empty() => return None,
+            Some(name) => {
+                params.insert(name.to_string(), percent_decode(segment)?);
+            }
+            None if part != segment => return None,
+            None => {}
+        }
+    }
+
+    match segments.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+/// Decodes `%XX` escapes in a path segment. Malformed escapes and bytes
+/// that aren't UTF-8 don't match any route.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let hex = rest
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        rest = &rest[2..];
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        handlers::tests::{app, store},
+        types,
+        web::FormValue,
+    };
+    use futures::executor::block_on;
+
+    fn endpoint(method: &str, path: &str) -> Option<Endpoint> {
+        find(method, path).map(|(endpoint, _)| endpoint)
+    }
+
+    #[test]
+    fn filenames_route_to_the_paste() {
+        let (found, params) = find("GET", "/abc/Makefile").unwrap();
+        assert_eq!(found, Endpoint::GetPaste);
+        assert_eq!(params["id"], "abc");
+        assert_eq!(params["filename"], "Makefile");
+
+        assert_eq!(
+            endpoint("POST", "/abc/Makefile"),
+            Some(Endpoint::RevealPaste)
+        );
+        assert_eq!(
+            endpoint("GET", "/raw/abc/Makefile"),
+            Some(Endpoint::RawPaste)
+        );
+        assert_eq!(
+            endpoint("POST", "/raw/abc/Makefile"),
+            Some(Endpoint::RevealRawPaste)
+        );
+    }
+
+    #[test]
+    fn parameters_are_percent_decoded() {
+        let (_, params) = find("GET", "/abc/My%20File.rs").unwrap();
+        assert_eq!(params["filename"], "My File.rs");
+
+        let (found, params) = find("PUT", "/my%2Bfile.c").unwrap();
+        assert_eq!(found, Endpoint::PutPaste);
+        assert_eq!(params["filename"], "my+file.c");
+
+        let (_, params) = find("GET", "/abc/na%C3%AFve.py").unwrap();
+        assert_eq!(params["filename"], "naïve.py");
+
+        assert!(find("GET", "/abc/100%").is_none());
+        assert!(find("GET", "/abc/%zz").is_none());
+        assert!(find("GET", "/abc/%+1").is_none());
+        assert!(find("GET", "/abc/%FF").is_none());
+    }
+
+    #[test]
+    fn static_routes_win_over_parameters() {
+        assert_eq!(endpoint("GET", "/"), Some(Endpoint::Index));
+        assert_eq!(endpoint("GET", "/api"), Some(Endpoint::Discovery));
+        assert_eq!(
+            endpoint("GET", "/api/languages"),
+            Some(Endpoint::ListLanguages)
+        );
+        assert_eq!(endpoint("GET", "/raw/abc"), Some(Endpoint::RawPaste));
+        assert_eq!(endpoint("PUT", "/notes.md"), Some(Endpoint::PutPaste));
+
+        assert_eq!(endpoint("DELETE", "/abc/Makefile"), None);
+        assert_eq!(endpoint("GET", "/abc/Makefile/more"), None);
+        assert_eq!(endpoint("GET", "/abc/"), None);
+    }
+
+    #[test]
+    fn confirmation_form_posts_back_to_a_route() {
+        let store = store();
+        let config = Config::from_lookup(|_| None);
+        let app = app(&store, &config);
+        let request = |method: &str, path: &str, accept: &str, form: &[(&str, &str)]| {
+            let (endpoint, params) = find(method, path).unwrap();
+            let req = Incoming {
+                params,
+                headers: vec![("accept".to_string(), accept.to_string())]
+                    .into_iter()
+                    .collect(),
+                form: form
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), FormValue::Field(value.to_string())))
+                    .collect(),
+                ..Incoming::default()
+            };
+            block_on(endpoint.call(&app, req))
+        };
+
+        let created = request(
+            "POST",
+            "/",
+            "application/json",
+            &[("content", "all:\n\tcargo build\n"), ("max_views", "2")],
+        );
+        let created: types::CodePaseResponse = serde_json::from_str(&created.body).unwrap();
+
+        let path = format!("/{}/Makefile", created.id);
+        let confirm = request("GET", &path, "text/html", &[]);
+        assert!(
+            confirm.body.contains(&format!("action=\"{path}\"")),
+            "{}",
+            confirm.body
+        );
+
+        let revealed = request("POST", &path, "application/json", &[]);
+        let view: types::PasteView = serde_json::from_str(&revealed.body).unwrap();
+        assert_eq!(view.content, "all:\n\tcargo build\n");
+    }
+}
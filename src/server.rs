@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use futures_executor::block_on;
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::{
+    config::Config,
+    error::PasteError,
+    handlers::{self, App},
+    routes,
+    store::PasteStore,
+    web::{is_urlencoded_form, FormValue, Incoming, Reply, FORM_FIELDS},
+};
+
+/// How often `maintain` runs while serving.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Where and how the server listens.
+pub struct Options {
+    /// Address to listen on, e.g. `127.0.0.1:8787`.
+    pub listen: String,
+    /// Reverse proxies whose `Host`, `X-Forwarded-Host` and
+    /// `X-Forwarded-Proto` headers are believed.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Serves the app over plain HTTP, one request at a time, until the
+/// process is stopped. `maintain` runs on start and then about once an
+/// hour, e.g. to purge expired entries from `store`.
+pub fn run<F: FnMut()>(
+    options: &Options,
+    store: &dyn PasteStore,
+    config: &Config,
+    mut maintain: F,
+) -> Result<()> {
+    let server = Server::http(&options.listen)
+        .map_err(|err| anyhow!("couldn't listen on {}: {}", options.listen, err))?;
+
+    maintain();
+    let mut maintained = Instant::now();
+
+    loop {
+        if let Some(request) = server.recv_timeout(Duration::from_secs(60))? {
+            handle(request, options, store, config);
+        }
+
+        if maintained.elapsed() >= MAINTENANCE_INTERVAL {
+            maintain();
+            maintained = Instant::now();
+        }
+    }
+}
+
+fn handle(mut request: Request, options: &Options, store: &dyn PasteStore, config: &Config) {
+    let app = App {
+        store,
+        config,
+        now: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+    };
+
+    let peer = request.remote_addr().map(|addr| addr.ip());
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or_default();
+    let headers: Vec<(String, String)> = request
+        .headers()
+        .iter()
+        .map(|header| (header.field.to_string(), header.value.to_string()))
+        .collect();
+
+    let (endpoint, params) = match routes::find(request.method().as_str(), path) {
+        Some((endpoint, params)) => (Some(endpoint), params),
+        None => (None, HashMap::new()),
+    };
+    let read_body = endpoint.map_or(false, |endpoint| endpoint.reads_body());
+
+    let reply = match incoming(
+        &url,
+        headers,
+        request.as_reader(),
+        params,
+        read_body,
+        config.max_request_size,
+    ) {
+        Ok(mut incoming) => {
+            incoming.origin = origin(&incoming.headers, peer, options);
+
+            match endpoint {
+                Some(endpoint) => {
+                    let headers = Incoming {
+                        headers: incoming.headers.clone(),
+                        ..Incoming::default()
+                    };
+                    block_on(handlers::guarded(
+                        &app,
+                        &headers,
+                        endpoint.call(&app, incoming),
+                    ))
+                }
+                None => app.fail(&incoming, PasteError::NotFound),
+            }
+        }
+        Err(err) => app.fail(
+            &Incoming::default(),
+            PasteError::BadRequest(format!("couldn't read request: {err}")),
+        ),
+    };
+
+    if let Err(err) = request.respond(into_response(reply, store)) {
+        log_error!("error sending response: {}", err);
+    }
+}
+
+/// Collects everything handlers need from a request, the way
+/// `web::incoming` does on Workers: the body is only read when
+/// `read_body` is set and never past `max_body` bytes, and forms are
+/// parsed into fields.
+fn incoming<R: Read>(
+    url: &str,
+    headers: Vec<(String, String)>,
+    body: R,
+    params: HashMap<String, String>,
+    read_body: bool,
+    max_body: usize,
+) -> io::Result<Incoming> {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let headers: HashMap<String, String> = headers
+        .into_iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .collect();
+
+    let mut incoming = Incoming {
+        params,
+        query: form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect(),
+        headers,
+        ..Incoming::default()
+    };
+
+    if !read_body {
+        return Ok(incoming);
+    }
+
+    let declared = incoming
+        .header("content-length")
+        .and_then(|value| value.parse::<usize>().ok());
+
+    if declared.map_or(false, |length| length > max_body) {
+        incoming.body_too_large = true;
+        return Ok(incoming);
+    }
+
+    let mut bytes = Vec::with_capacity(declared.unwrap_or_default());
+    body.take(max_body as u64 + 1).read_to_end(&mut bytes)?;
+
+    if bytes.len() > max_body {
+        incoming.body_too_large = true;
+        return Ok(incoming);
+    }
+
+    match incoming.content_type().as_deref() {
+        Some("application/x-www-form-urlencoded") if is_urlencoded_form(&bytes) => {
+            incoming.form = form_urlencoded::parse(&bytes)
+                .filter(|(name, _)| FORM_FIELDS.contains(&name.as_ref()))
+                .map(|(name, value)| (name.into_owned(), FormValue::Field(value.into_owned())))
+                .collect();
+
+            // A form without `content` may still be a raw upload.
+            if !incoming.form.contains_key("content") {
+                incoming.body = bytes;
+            }
+        }
+        Some("multipart/form-data") => {
+            let form = incoming
+                .header("content-type")
+                .and_then(|value| parse_multipart(value, &bytes));
+
+            match form {
+                Some(form) => incoming.form = form,
+                None => incoming.body = bytes,
+            }
+        }
+        _ => incoming.body = bytes,
+    }
+
+    Ok(incoming)
+}
+
+/// The origin permalinks are built on unless `PASTE_BASE_URL` is set.
+/// `Host` and `X-Forwarded-*` are up to the client, so they're only believed
+/// from a trusted proxy: anyone else could plant their own host in the
+/// links handed out. Other requests get the listen address.
+fn origin(headers: &HashMap<String, String>, peer: Option<IpAddr>, options: &Options) -> String {
+    let fallback = format!("http://{}", options.listen);
+
+    if !peer.map_or(false, |peer| options.trusted_proxies.contains(&peer)) {
+        return fallback;
+    }
+
+    let scheme = match headers.get("x-forwarded-proto").map(String::as_str) {
+        Some("https") => "https",
+        _ => "http",
+    };
+    let host = headers
+        .get("x-forwarded-host")
+        .or_else(|| headers.get("host"))
+        .filter(|host| {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+        });
+
+    match host {
+        Some(host) => format!("{scheme}://{host}"),
+        None => fallback,
+    }
+}
+
+/// Splits a `multipart/form-data` body into the fields handlers know, or
+/// returns `None` when it isn't well-formed.
+fn parse_multipart(content_type: &str, body: &[u8]) -> Option<HashMap<String, FormValue>> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("--{boundary}");
+    let separator = format!("\r\n{delimiter}");
+
+    let mut form = HashMap::new();
+    let mut rest = &body[find(body, delimiter.as_bytes())? + delimiter.len()..];
+
+    // Each part is `\r\n<headers>\r\n\r\n<content>\r\n--<boundary>`, and the
+    // last delimiter is followed by `--`.
+    while !rest.starts_with(b"--") {
+        rest = rest.strip_prefix(b"\r\n")?;
+
+        let end = find(rest, separator.as_bytes())?;
+        let part = &rest[..end];
+        rest = &rest[end + separator.len()..];
+
+        let header_end = find(part, b"\r\n\r\n")?;
+        let headers = std::str::from_utf8(&part[..header_end]).ok()?;
+        let content = &part[header_end + 4..];
+
+        let disposition = headers.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("content-disposition")
+                .then_some(value)
+        })?;
+        let param = |key: &str| {
+            disposition
+                .split(';')
+                .find_map(|param| param.trim().strip_prefix(key)?.strip_prefix('='))
+                .map(|value| value.trim_matches('"').to_string())
+        };
+
+        let name = param("name")?;
+        if !FORM_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+
+        let value = match param("filename") {
+            Some(filename) => FormValue::File {
+                name: filename,
+                bytes: content.to_vec(),
+            },
+            None => FormValue::Field(String::from_utf8(content.to_vec()).ok()?),
+        };
+        form.insert(name, value);
+    }
+
+    Some(form)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Converts a reply into a response. Streamed replies are read from
+/// `store` up front, since requests are served one at a time anyway.
+fn into_response(reply: Reply, store: &dyn PasteStore) -> Response<Cursor<Vec<u8>>> {
+    let body = match reply.stream {
+        Some(keys) => {
+            let mut body = Vec::new();
+            for key in keys {
+                match block_on(store.get(&key)) {
+                    Ok(Some(stored)) => body.extend_from_slice(stored.value.as_bytes()),
+                    result => {
+                        if let Err(err) = result {
+                            log_error!("error reading {}: {:?}", key, err.to_string());
+                        }
+                        let error = PasteError::Storage("couldn't read paste".to_string());
+                        return into_response(
+                            Reply::json(error.status(), &error.to_response()),
+                            store,
+                        );
+                    }
+                }
+            }
+            body
+        }
+        None => reply.body.into_bytes(),
+    };
+
+    let mut response = Response::from_data(body).with_status_code(reply.status);
+    for (name, value) in &reply.headers {
+        // The length is worked out from the body.
+        if name.eq_ignore_ascii_case("content-length") {
+            continue;
+        }
+        if let Ok(header) = Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+            response.add_header(header);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handlers::tests::store, store::PutOptions};
+
+    fn read(headers: &[(&str, &str)], body: &[u8], max_body: usize) -> Incoming {
+        incoming(
+            "/?expiry=1h&burn_after_read=on",
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body,
+            HashMap::new(),
+            true,
+            max_body,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn requests_are_reduced_to_incoming() {
+        let req = read(
+            &[("Content-Type", "application/x-www-form-urlencoded")],
+            b"content=a%3Db&language=rs&ignored=1",
+            1024,
+        );
+
+        assert_eq!(req.query("expiry"), Some("1h"));
+        assert_eq!(req.header("content-type"), req.header("Content-Type"));
+        assert_eq!(req.field("content"), Some("a=b"));
+        assert_eq!(req.field("language"), Some("rs"));
+        assert!(!req.form.contains_key("ignored"));
+        assert!(req.body.is_empty());
+
+        // `curl --data-binary @script.py` is a urlencoded form without
+        // `content`, which is left to the handler.
+        let raw = read(
+            &[("Content-Type", "application/x-www-form-urlencoded")],
+            b"print('a=b')",
+            1024,
+        );
+        assert_eq!(raw.body, b"print('a=b')");
+
+        // Nor is a file that merely contains `content=` cut down to it.
+        let file = b"x = 1\n&content=gotcha\n";
+        let raw = read(
+            &[("Content-Type", "application/x-www-form-urlencoded")],
+            file,
+            1024,
+        );
+        assert!(raw.form.is_empty());
+        assert_eq!(raw.body, file);
+    }
+
+    #[test]
+    fn only_trusted_proxies_pick_the_origin() {
+        let options = Options {
+            listen: "127.0.0.1:8787".to_string(),
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+        };
+        let headers: HashMap<String, String> = [
+            ("host", "internal:8787"),
+            ("x-forwarded-host", "paste.example.com"),
+            ("x-forwarded-proto", "https"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let from = |peer: &str, headers: &HashMap<String, String>| {
+            origin(headers, Some(peer.parse().unwrap()), &options)
+        };
+
+        assert_eq!(from("10.0.0.1", &headers), "https://paste.example.com");
+        assert_eq!(from("203.0.113.9", &headers), "http://127.0.0.1:8787");
+        assert_eq!(origin(&headers, None, &options), "http://127.0.0.1:8787");
+
+        let mut forged = headers.clone();
+        forged.insert(
+            "x-forwarded-host".to_string(),
+            "evil.example/phish?".to_string(),
+        );
+        assert_eq!(from("10.0.0.1", &forged), "http://127.0.0.1:8787");
+
+        let mut host_only = headers;
+        host_only.remove("x-forwarded-host");
+        host_only.insert("x-forwarded-proto".to_string(), "gopher".to_string());
+        assert_eq!(from("10.0.0.1", &host_only), "http://internal:8787");
+    }
+
+    #[test]
+    fn oversized_bodies_are_not_read() {
+        let declared = read(&[("Content-Length", "5")], b"12345", 4);
+        assert!(declared.body_too_large);
+
+        let streamed = read(&[], b"12345", 4);
+        assert!(streamed.body_too_large);
+        assert!(streamed.body.is_empty());
+    }
+
+    #[test]
+    fn multipart_forms_are_parsed() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"content\"; filename=\"main.rs\"\r\n\
+            Content-Type: text/x-rust\r\n\r\n\
+            fn main() {}\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"expiry\"\r\n\r\n\
+            1d\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"other\"\r\n\r\n\
+            x\r\n--XyZ--\r\n";
+        let req = read(
+            &[("Content-Type", "multipart/form-data; boundary=\"XyZ\"")],
+            body,
+            1024,
+        );
+
+        match req.form.get("content") {
+            Some(FormValue::File { name, bytes }) => {
+                assert_eq!(name, "main.rs");
+                assert_eq!(bytes, b"fn main() {}");
+            }
+            _ => panic!("expected a file"),
+        }
+        assert_eq!(req.field("expiry"), Some("1d"));
+        assert!(!req.form.contains_key("other"));
+        assert!(req.body.is_empty());
+
+        assert!(parse_multipart("multipart/form-data; boundary=XyZ", b"--XyZ\r\nbroken").is_none());
+    }
+
+    #[test]
+    fn streamed_replies_are_read_from_the_store() {
+        let store = store();
+        for (key, value) in [("chunk:a:0", "01"), ("chunk:a:1", "23")] {
+            block_on(store.put(key, value.to_string(), PutOptions::default())).unwrap();
+        }
+
+        let reply = Reply::stream(
+            "text/plain",
+            vec!["chunk:a:0".to_string(), "chunk:a:1".to_string()],
+        );
+        let response = into_response(reply, &store);
+        assert_eq!(response.status_code().0, 200);
+
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "0123");
+
+        let missing = Reply::stream("text/plain", vec!["chunk:b:0".to_string()]);
+        assert_eq!(into_response(missing, &store).status_code().0, 503);
+    }
+}
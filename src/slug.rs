@@ -0,0 +1,73 @@
+use anyhow::{bail, Result};
+
+pub const MIN_LENGTH: usize = 3;
+pub const MAX_LENGTH: usize = 64;
+
+/// Paths the router serves itself, or may serve later, so a slug can never
+/// shadow them.
+const RESERVED: &[&str] = &[
+    "about", "admin", "api", "assets", "delete", "edit", "favicon", "health", "help", "languages",
+    "login", "logout", "new", "paste", "raw", "robots", "static", "themes",
+];
+
+/// Key under which the owner of a slug is remembered. It outlives the paste,
+/// so a slug can't be taken over once its paste expires or is deleted.
+pub fn reservation_key(slug: &str) -> String {
+    format!("slug:{slug}")
+}
+
+/// Checks that `slug` is lowercase ASCII letters, digits and inner dashes,
+/// within the length range and not a reserved word.
+pub fn validate(slug: &str) -> Result<()> {
+    if slug.len() < MIN_LENGTH || slug.len() > MAX_LENGTH {
+        bail!(
+            "slug must be between {} and {} characters",
+            MIN_LENGTH,
+            MAX_LENGTH
+        );
+    }
+
+    if !slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        bail!("slug may only contain lowercase letters, digits and dashes");
+    }
+
+    if slug.starts_with('-') || slug.ends_with('-') || slug.contains("--") {
+        bail!("slug can't start or end with a dash, or repeat one");
+    }
+
+    if RESERVED.contains(&slug) {
+        bail!("slug '{}' is reserved", slug);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_readable_slugs() {
+        assert!(validate("deploy-checklist").is_ok());
+        assert!(validate("k8s-101").is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_characters_and_lengths() {
+        assert!(validate("ab").is_err());
+        assert!(validate(&"a".repeat(MAX_LENGTH + 1)).is_err());
+        assert!(validate("Deploy").is_err());
+        assert!(validate("deploy.rs").is_err());
+        assert!(validate("-deploy").is_err());
+        assert!(validate("deploy--list").is_err());
+    }
+
+    #[test]
+    fn rejects_route_names() {
+        assert!(validate("api").is_err());
+        assert!(validate("raw").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use worker::async_trait::async_trait;
+
+use super::{unix_now, ListPage, ListedKey, PasteStore, PutOptions, StoredValue};
+use crate::types::PasteMetadata;
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    key: String,
+    value: String,
+    metadata: Option<PasteMetadata>,
+    expires_at: Option<u64>,
+}
+
+/// What [`FsPasteStore::list`] needs of an entry, kept in memory.
+struct Listed {
+    expires_at: Option<u64>,
+    metadata: Option<PasteMetadata>,
+}
+
+/// [`PasteStore`] that keeps one JSON file per key below a directory,
+/// sharded by a hash prefix so no single directory grows too large.
+///
+/// File names are hashes, so listing goes through an index of the keys
+/// built when the store is opened. A directory must therefore only be
+/// served by one process at a time.
+pub struct FsPasteStore {
+    root: PathBuf,
+    index: Mutex<BTreeMap<String, Listed>>,
+}
+
+impl FsPasteStore {
+    pub fn open<P: Into<PathBuf>>(root: P) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root).with_context(|| format!("couldn't create {}", root.display()))?;
+
+        let store = FsPasteStore {
+            root,
+            index: Mutex::new(BTreeMap::new()),
+        };
+
+        let mut index = BTreeMap::new();
+        for path in store.files()? {
+            if let Some(entry) = FsPasteStore::read(&path)? {
+                index.insert(
+                    entry.key,
+                    Listed {
+                        expires_at: entry.expires_at,
+                        metadata: entry.metadata,
+                    },
+                );
+            }
+        }
+        *store.index.lock().unwrap() = index;
+
+        Ok(store)
+    }
+
+    /// `<root>/<first 2 hex chars>/<sha256(key) in hex>.json`. Hashing keeps
+    /// arbitrary keys (e.g. `tombstone:abc`) safe as file names, and of the
+    /// same length however long the key.
+    fn path_for(&self, key: &str) -> PathBuf {
+        let name: String = Sha256::digest(key.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        self.root.join(&name[..2]).join(format!("{name}.json"))
+    }
+
+    fn read(path: &Path) -> Result<Option<Entry>> {
+        match fs::read_to_string(path) {
+            Ok(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("couldn't read {}", path.display())),
+        }
+    }
+
+    fn is_live(entry: &Entry) -> bool {
+        entry
+            .expires_at
+            .map_or(true, |expires_at| expires_at > unix_now())
+    }
+
+    /// Removes the files of expired entries, which `get` and `list` treat
+    /// as missing anyway, to free disk space.
+    pub fn purge_expired(&self) -> Result<usize> {
+        let now = unix_now();
+        let mut index = self.index.lock().unwrap();
+        let expired: Vec<String> = index
+            .iter()
+            .filter(|(_, listed)| listed.expires_at.map_or(false, |at| at <= now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &expired {
+            match fs::remove_file(self.path_for(key)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => index.remove(key),
+            };
+        }
+
+        Ok(expired.len())
+    }
+
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+
+        for shard in fs::read_dir(&self.root)? {
+            let shard = shard?.path();
+            if !shard.is_dir() {
+                continue;
+            }
+
+            for file in fs::read_dir(&shard)? {
+                let file = file?.path();
+                if file.extension().map_or(false, |ext| ext == "json") {
+                    files.push(file);
+                }
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+#[async_trait(?Send)]
+impl PasteStore for FsPasteStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredValue>> {
+        Ok(FsPasteStore::read(&self.path_for(key))?
+            .filter(FsPasteStore::is_live)
+            .map(|entry| StoredValue {
+                value: entry.value,
+                metadata: entry.metadata,
+            }))
+    }
+
+    async fn put(&self, key: &str, value: String, options: PutOptions) -> Result<()> {
+        let path = self.path_for(key);
+        let entry = Entry {
+            key: key.to_string(),
+            value,
+            metadata: options.metadata,
+            expires_at: options.expires_at,
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a sibling file and rename over the target, so readers
+        // never see a half-written entry.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&entry)?)?;
+        fs::rename(&tmp, &path)?;
+
+        self.index.lock().unwrap().insert(
+            entry.key,
+            Listed {
+                expires_at: entry.expires_at,
+                metadata: entry.metadata,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path_for(key)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => {
+                self.index.lock().unwrap().remove(key);
+                Ok(())
+            }
+        }
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<String>, limit: u64) -> Result<ListPage> {
+        let now = unix_now();
+        let start = match &cursor {
+            Some(cursor) if cursor.as_str() >= prefix => Bound::Excluded(cursor.as_str()),
+            _ => Bound::Included(prefix),
+        };
+
+        let index = self.index.lock().unwrap();
+        let mut keys: Vec<ListedKey> = index
+            .range::<str, _>((start, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, listed)| listed.expires_at.map_or(true, |at| at > now))
+            .take(limit as usize + 1)
+            .map(|(key, listed)| ListedKey {
+                name: key.clone(),
+                expires_at: listed.expires_at,
+                metadata: listed.metadata.clone(),
+            })
+            .collect();
+
+        let cursor = if keys.len() > limit as usize {
+            keys.truncate(limit as usize);
+            keys.last().map(|key| key.name.clone())
+        } else {
+            None
+        };
+
+        Ok(ListPage { keys, cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conforms_to_paste_store() {
+        let root = std::env::temp_dir().join(format!("paste-store-{}", crate::token::generate()));
+        let store = FsPasteStore::open(&root).unwrap();
+
+        super::super::tests::conformance(&store, unix_now());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn long_keys_are_stored_and_reindexed() {
+        let root = std::env::temp_dir().join(format!("paste-store-{}", crate::token::generate()));
+        let store = FsPasteStore::open(&root).unwrap();
+        let key = format!("tombstone:{}", "a".repeat(300));
+
+        futures::executor::block_on(async {
+            store
+                .put(&key, "gone".to_string(), PutOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(store.get(&key).await.unwrap().unwrap().value, "gone");
+        });
+
+        // A reopened store finds the keys already on disk.
+        let reopened = FsPasteStore::open(&root).unwrap();
+        let page = futures::executor::block_on(reopened.list("tombstone:", None, 10)).unwrap();
+        assert_eq!(page.keys.len(), 1);
+        assert_eq!(page.keys[0].name, key);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use worker::async_trait::async_trait;
+use worker::kv::KvStore;
+use worker::Date;
+
+use super::{ListPage, ListedKey, PasteStore, PutOptions, StoredValue};
+use crate::{expiry, types::PasteMetadata};
+
+/// [`PasteStore`] backed by the `code_paste` Workers KV namespace.
+pub struct KvPasteStore {
+    kv: KvStore,
+}
+
+impl KvPasteStore {
+    pub fn new(kv: KvStore) -> Self {
+        KvPasteStore { kv }
+    }
+}
+
+#[async_trait(?Send)]
+impl PasteStore for KvPasteStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredValue>> {
+        let (value, metadata) = self
+            .kv
+            .get(key)
+            .text_with_metadata::<PasteMetadata>()
+            .await
+            .map_err(|err| anyhow!("error reading {} from kv: {}", key, err))?;
+
+        Ok(value.map(|value| StoredValue { value, metadata }))
+    }
+
+    async fn put(&self, key: &str, value: String, options: PutOptions) -> Result<()> {
+        let mut put = self
+            .kv
+            .put(key, value)
+            .map_err(|err| anyhow!("error writing {} to kv: {}", key, err))?;
+
+        if let Some(metadata) = options.metadata {
+            put = put
+                .metadata(metadata)
+                .map_err(|err| anyhow!("error serializing metadata: {}", err))?;
+        }
+
+        if let Some(expires_at) = options.expires_at {
+            // KV rejects expirations less than a minute away.
+            let earliest = Date::now().as_millis() / 1000 + expiry::MIN_TTL;
+            put = put.expiration(expires_at.max(earliest));
+        }
+
+        put.execute()
+            .await
+            .map_err(|err| anyhow!("error writing {} to kv: {}", key, err))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.kv
+            .delete(key)
+            .await
+            .map_err(|err| anyhow!("error deleting {} from kv: {}", key, err))
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<String>, limit: u64) -> Result<ListPage> {
+        let mut list = self.kv.list().prefix(prefix.to_string()).limit(limit);
+
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor);
+        }
+
+        let page = list
+            .execute()
+            .await
+            .map_err(|err| anyhow!("error listing kv keys: {}", err))?;
+
+        let list_complete = page.list_complete;
+
+        Ok(ListPage {
+            keys: page
+                .keys
+                .into_iter()
+                .map(|key| ListedKey {
+                    name: key.name,
+                    expires_at: key.expiration,
+                    metadata: key
+                        .metadata
+                        .and_then(|value| serde_json::from_value(value).ok()),
+                })
+                .collect(),
+            cursor: page.cursor.filter(|_| !list_complete),
+        })
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use worker::async_trait::async_trait;
+
+use super::{ListPage, ListedKey, PasteStore, PutOptions, StoredValue};
+use crate::types::PasteMetadata;
+
+struct Entry {
+    value: String,
+    metadata: Option<PasteMetadata>,
+    expires_at: Option<u64>,
+}
+
+/// [`PasteStore`] that keeps everything in process memory. Time is driven
+/// by hand through [`MemoryPasteStore::set_now`], so expiry is deterministic.
+#[derive(Default)]
+pub struct MemoryPasteStore {
+    entries: RefCell<BTreeMap<String, Entry>>,
+    now: Cell<u64>,
+}
+
+impl MemoryPasteStore {
+    pub fn new(now: u64) -> Self {
+        MemoryPasteStore {
+            entries: RefCell::default(),
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn set_now(&self, now: u64) {
+        self.now.set(now);
+    }
+
+    fn is_live(&self, entry: &Entry) -> bool {
+        entry
+            .expires_at
+            .map_or(true, |expires_at| expires_at > self.now.get())
+    }
+}
+
+#[async_trait(?Send)]
+impl PasteStore for MemoryPasteStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredValue>> {
+        Ok(self
+            .entries
+            .borrow()
+            .get(key)
+            .filter(|entry| self.is_live(entry))
+            .map(|entry| StoredValue {
+                value: entry.value.clone(),
+                metadata: entry.metadata.clone(),
+            }))
+    }
+
+    async fn put(&self, key: &str, value: String, options: PutOptions) -> Result<()> {
+        self.entries.borrow_mut().insert(
+            key.to_string(),
+            Entry {
+                value,
+                metadata: options.metadata,
+                expires_at: options.expires_at,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.entries.borrow_mut().remove(key);
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<String>, limit: u64) -> Result<ListPage> {
+        let entries = self.entries.borrow();
+        let mut keys = entries
+            .iter()
+            .filter(|(name, entry)| name.starts_with(prefix) && self.is_live(entry))
+            .filter(|(name, _)| cursor.as_ref().map_or(true, |cursor| *name > cursor))
+            .take(limit as usize + 1)
+            .map(|(name, entry)| ListedKey {
+                name: name.clone(),
+                expires_at: entry.expires_at,
+                metadata: entry.metadata.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let cursor = if keys.len() > limit as usize {
+            keys.truncate(limit as usize);
+            keys.last().map(|key| key.name.clone())
+        } else {
+            None
+        };
+
+        Ok(ListPage { keys, cursor })
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::Result;
+use worker::async_trait::async_trait;
+
+use crate::types::PasteMetadata;
+
+#[cfg(feature = "fs-store")]
+mod fs;
+mod kv;
+mod memory;
+#[cfg(feature = "sqlite-store")]
+mod sqlite;
+
+#[cfg(feature = "fs-store")]
+pub use self::fs::FsPasteStore;
+pub use self::kv::KvPasteStore;
+pub use self::memory::MemoryPasteStore;
+#[cfg(feature = "sqlite-store")]
+pub use self::sqlite::SqlitePasteStore;
+
+/// A value read back from a [`PasteStore`].
+pub struct StoredValue {
+    pub value: String,
+    pub metadata: Option<PasteMetadata>,
+}
+
+#[derive(Default)]
+pub struct PutOptions {
+    pub metadata: Option<PasteMetadata>,
+    /// Absolute expiry as a unix timestamp. The entry disappears once it
+    /// has passed.
+    pub expires_at: Option<u64>,
+}
+
+pub struct ListedKey {
+    pub name: String,
+    pub expires_at: Option<u64>,
+    pub metadata: Option<PasteMetadata>,
+}
+
+pub struct ListPage {
+    pub keys: Vec<ListedKey>,
+    /// Pass back into [`PasteStore::list`] to fetch the next page. `None`
+    /// once the listing is complete.
+    pub cursor: Option<String>,
+}
+
+/// Key-value storage for pastes, tombstones and anything else the handlers
+/// keep between requests.
+#[async_trait(?Send)]
+pub trait PasteStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredValue>>;
+
+    async fn put(&self, key: &str, value: String, options: PutOptions) -> Result<()>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    async fn list(&self, prefix: &str, cursor: Option<String>, limit: u64) -> Result<ListPage>;
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+}
+
+/// Wall-clock time for the self-hosted backends, which enforce expiry
+/// themselves instead of relying on KV.
+#[cfg(any(feature = "fs-store", feature = "sqlite-store"))]
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn metadata(size: usize) -> PasteMetadata {
+        PasteMetadata {
+            version: 1,
+            delete_token_hash: "hash".to_string(),
+            expires_at: None,
+            views_remaining: None,
+            language: Some("rs".to_string()),
+            filename: None,
+            created_at: None,
+            size,
+            visibility: Default::default(),
+        }
+    }
+
+    /// Behaviour every backend has to share. `now` is the backend's current
+    /// time, used to write entries that are already expired.
+    pub(super) fn conformance(store: &dyn PasteStore, now: u64) {
+        block_on(async {
+            store
+                .put(
+                    "paste:a",
+                    "first".to_string(),
+                    PutOptions {
+                        metadata: Some(metadata(5)),
+                        expires_at: Some(now + 3600),
+                    },
+                )
+                .await
+                .unwrap();
+            store
+                .put("paste:b", "second".to_string(), PutOptions::default())
+                .await
+                .unwrap();
+            store
+                .put("paste:c", "third".to_string(), PutOptions::default())
+                .await
+                .unwrap();
+            store
+                .put("other", "unrelated".to_string(), PutOptions::default())
+                .await
+                .unwrap();
+            store
+                .put(
+                    "paste:expired",
+                    "stale".to_string(),
+                    PutOptions {
+                        metadata: None,
+                        expires_at: Some(now - 1),
+                    },
+                )
+                .await
+                .unwrap();
+
+            let first = store.get("paste:a").await.unwrap().unwrap();
+            assert_eq!(first.value, "first");
+            assert_eq!(first.metadata.unwrap().size, 5);
+
+            assert!(store.get("paste:expired").await.unwrap().is_none());
+            assert!(!store.exists("missing").await.unwrap());
+
+            store
+                .put("paste:b", "replaced".to_string(), PutOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(
+                store.get("paste:b").await.unwrap().unwrap().value,
+                "replaced"
+            );
+
+            let page = store.list("paste:", None, 2).await.unwrap();
+            let names: Vec<_> = page.keys.iter().map(|key| key.name.as_str()).collect();
+            assert_eq!(names, ["paste:a", "paste:b"]);
+            assert_eq!(page.keys[0].expires_at, Some(now + 3600));
+
+            let rest = store.list("paste:", page.cursor, 2).await.unwrap();
+            let names: Vec<_> = rest.keys.iter().map(|key| key.name.as_str()).collect();
+            assert_eq!(names, ["paste:c"]);
+            assert!(rest.cursor.is_none());
+
+            store.delete("paste:a").await.unwrap();
+            store.delete("never-existed").await.unwrap();
+            assert!(store.get("paste:a").await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn memory_store_conforms() {
+        conformance(&MemoryPasteStore::new(1_000), 1_000);
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use worker::async_trait::async_trait;
+
+use super::{unix_now, ListPage, ListedKey, PasteStore, PutOptions, StoredValue};
+
+/// [`PasteStore`] kept in a single SQLite database, for self-hosted
+/// instances that don't run on Cloudflare.
+pub struct SqlitePasteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqlitePasteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let conn = Connection::open(path.as_ref())
+            .with_context(|| format!("couldn't open {}", path.as_ref().display()))?;
+
+        SqlitePasteStore::with_connection(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        SqlitePasteStore::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pastes (
+                key        TEXT PRIMARY KEY NOT NULL,
+                value      TEXT NOT NULL,
+                metadata   TEXT,
+                expires_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS pastes_expires_at ON pastes (expires_at);",
+        )?;
+
+        Ok(SqlitePasteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Deletes rows whose expiry has passed and returns how many went.
+    pub fn purge_expired(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        Ok(conn.execute(
+            "DELETE FROM pastes WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            params![unix_now()],
+        )?)
+    }
+}
+
+/// Escapes `LIKE` wildcards so a prefix only matches literally.
+fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[async_trait(?Send)]
+impl PasteStore for SqlitePasteStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredValue>> {
+        let conn = self.conn.lock().unwrap();
+
+        let row = conn
+            .query_row(
+                "SELECT value, metadata FROM pastes
+                 WHERE key = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+                params![key, unix_now()],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()?;
+
+        Ok(row.map(|(value, metadata)| StoredValue {
+            value,
+            metadata: metadata.and_then(|metadata| serde_json::from_str(&metadata).ok()),
+        }))
+    }
+
+    async fn put(&self, key: &str, value: String, options: PutOptions) -> Result<()> {
+        let metadata = match options.metadata {
+            Some(metadata) => Some(serde_json::to_string(&metadata)?),
+            None => None,
+        };
+
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO pastes (key, value, metadata, expires_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![key, value, metadata, options.expires_at],
+        )?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM pastes WHERE key = ?1", params![key])?;
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<String>, limit: u64) -> Result<ListPage> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT key, metadata, expires_at FROM pastes
+             WHERE key LIKE ?1 ESCAPE '\\' AND key > ?2
+               AND (expires_at IS NULL OR expires_at > ?3)
+             ORDER BY key LIMIT ?4",
+        )?;
+
+        let mut keys = statement
+            .query_map(
+                params![
+                    like_prefix(prefix),
+                    cursor.unwrap_or_default(),
+                    unix_now(),
+                    limit + 1
+                ],
+                |row| {
+                    Ok(ListedKey {
+                        name: row.get(0)?,
+                        metadata: row
+                            .get::<_, Option<String>>(1)?
+                            .and_then(|metadata| serde_json::from_str(&metadata).ok()),
+                        expires_at: row.get(2)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let cursor = if keys.len() > limit as usize {
+            keys.truncate(limit as usize);
+            keys.last().map(|key| key.name.clone())
+        } else {
+            None
+        };
+
+        Ok(ListPage { keys, cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conforms_to_paste_store() {
+        super::super::tests::conformance(&SqlitePasteStore::open_in_memory().unwrap(), unix_now());
+    }
+}
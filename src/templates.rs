@@ -0,0 +1,231 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use anyhow::Result;
+use serde::Serialize;
+use tinytemplate::TinyTemplate;
+
+use crate::types::LayoutContext;
+
+/// The templates every page is built from, by name. `layout` wraps each
+/// page, `expiry` is a partial pulled in with `{{ call expiry with expires }}`.
+const BUILTIN: &[(&str, &str)] = &[
+    ("layout", include_str!("../templates/layout.html")),
+    ("index", include_str!("../templates/index.html")),
+    ("paste", include_str!("../templates/paste.html")),
+    ("expiry", include_str!("../templates/expiry.html")),
+    ("confirm", include_str!("../templates/confirm.html")),
+    ("error", include_str!("../templates/error.html")),
+];
+
+/// Names of the templates an operator may override.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTIN.iter().map(|(name, _)| *name)
+}
+
+/// Overrides and the templates compiled from them.
+type Compiled = (HashMap<String, String>, Rc<Templates<'static>>);
+
+thread_local! {
+    static BUILTIN_TEMPLATES: Rc<Templates<'static>> = Rc::new(Templates::builtin());
+
+    /// The templates compiled for the last overrides seen, along with them.
+    static OVERRIDDEN_TEMPLATES: RefCell<Option<Compiled>> = RefCell::new(None);
+}
+
+/// Compiled page templates. Values are HTML-escaped unless a template
+/// asks for `{value | unescaped}`.
+pub struct Templates<'a> {
+    inner: TinyTemplate<'a>,
+    overridden: bool,
+}
+
+impl<'a> Templates<'a> {
+    /// Compiles the built-in templates, with `overrides` taking the place
+    /// of any built-in of the same name. An override that doesn't compile
+    /// is logged and the built-in is used instead.
+    pub fn new(overrides: &'a HashMap<String, String>) -> Self {
+        let mut inner = TinyTemplate::new();
+        let mut overridden = false;
+
+        for (name, builtin) in BUILTIN {
+            if let Some(text) = overrides.get(*name) {
+                match inner.add_template(name, text) {
+                    Ok(()) => {
+                        overridden = true;
+                        continue;
+                    }
+                    Err(err) => log_error!("error compiling template '{}': {}", name, err),
+                }
+            }
+
+            inner
+                .add_template(name, builtin)
+                .expect("built-in templates compile");
+        }
+
+        Templates { inner, overridden }
+    }
+
+    /// The templates for `overrides`, compiled on first use and reused by
+    /// later requests in the same isolate. Overrides come from the
+    /// environment, so they only change with a deploy; the compiled set
+    /// borrows them for good, and a replaced set leaks its overrides.
+    pub fn cached(overrides: &HashMap<String, String>) -> Rc<Templates<'static>> {
+        if overrides.is_empty() {
+            return BUILTIN_TEMPLATES.with(Rc::clone);
+        }
+
+        OVERRIDDEN_TEMPLATES.with(|cache| {
+            let mut cache = cache.borrow_mut();
+
+            match &*cache {
+                Some((cached, templates)) if cached == overrides => templates.clone(),
+                _ => {
+                    let leaked: &'static HashMap<String, String> =
+                        Box::leak(Box::new(overrides.clone()));
+                    let templates = Rc::new(Templates::new(leaked));
+                    *cache = Some((overrides.clone(), templates.clone()));
+                    templates
+                }
+            }
+        })
+    }
+
+    fn builtin() -> Templates<'static> {
+        let mut inner = TinyTemplate::new();
+        for (name, builtin) in BUILTIN {
+            inner
+                .add_template(name, builtin)
+                .expect("built-in templates compile");
+        }
+
+        Templates {
+            inner,
+            overridden: false,
+        }
+    }
+
+    /// Renders the page `name` and wraps it in the layout.
+    pub fn render<C: Serialize>(
+        &self,
+        name: &str,
+        context: &C,
+        title: &str,
+        style: Option<String>,
+        noindex: bool,
+    ) -> Result<String> {
+        let body = self.render_one(name, context)?;
+
+        self.render_one(
+            "layout",
+            &LayoutContext {
+                title: title.to_string(),
+                style,
+                noindex,
+                body,
+            },
+        )
+    }
+
+    /// Renders a single template. Overrides only fail at this point when
+    /// they refer to values the context doesn't have, so the built-ins are
+    /// tried before giving up.
+    fn render_one<C: Serialize>(&self, name: &str, context: &C) -> Result<String> {
+        match self.inner.render(name, context) {
+            Ok(page) => Ok(page),
+            Err(err) if self.overridden => {
+                log_error!("error rendering template '{}': {}", name, err);
+                Ok(BUILTIN_TEMPLATES.with(|builtin| builtin.inner.render(name, context))?)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ErrorTemplateContext;
+
+    fn error_page(templates: &Templates) -> String {
+        templates
+            .render(
+                "error",
+                &ErrorTemplateContext {
+                    status: 404,
+                    code: "not_found".to_string(),
+                    message: "<script>alert(1)</script>".to_string(),
+                },
+                "404",
+                None,
+                false,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn builtins_render_inside_the_layout() {
+        let overrides = HashMap::new();
+        let page = error_page(&Templates::new(&overrides));
+
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>404</title>"));
+        assert!(page.contains("<h1>404</h1>"));
+        assert!(!page.contains("<style>"));
+    }
+
+    #[test]
+    fn values_are_escaped() {
+        let overrides = HashMap::new();
+        let page = error_page(&Templates::new(&overrides));
+
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!page.contains("<script>"));
+    }
+
+    #[test]
+    fn overrides_replace_builtins() {
+        let overrides = HashMap::from([(
+            "error".to_string(),
+            "<p class=\"oops\">{status}: {message}</p>".to_string(),
+        )]);
+        let page = error_page(&Templates::new(&overrides));
+
+        assert!(page.contains("<p class=\"oops\">404: &lt;script&gt;"));
+        assert!(!page.contains("<h1>"));
+    }
+
+    #[test]
+    fn compiled_templates_are_reused() {
+        let none = HashMap::new();
+        assert!(Rc::ptr_eq(
+            &Templates::cached(&none),
+            &Templates::cached(&none)
+        ));
+
+        let overrides = HashMap::from([("error".to_string(), "<p>{status}</p>".to_string())]);
+        let first = Templates::cached(&overrides);
+        assert!(Rc::ptr_eq(&first, &Templates::cached(&overrides.clone())));
+        assert!(error_page(&first).contains("<p>404</p>"));
+
+        let changed = HashMap::from([("error".to_string(), "<p>{code}</p>".to_string())]);
+        assert!(error_page(&Templates::cached(&changed)).contains("<p>not_found</p>"));
+    }
+
+    #[test]
+    fn broken_overrides_fall_back_to_builtins() {
+        let overrides = HashMap::from([
+            ("error".to_string(), "{{ if status }}unclosed".to_string()),
+            (
+                "layout".to_string(),
+                "{missing}{body | unescaped}".to_string(),
+            ),
+        ]);
+        let page = error_page(&Templates::new(&overrides));
+
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<h1>404</h1>"));
+    }
+}
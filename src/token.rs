@@ -0,0 +1,120 @@
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
+
+const TOKEN_LENGTH: usize = 32;
+
+/// Mints a new secret deletion token. Only its hash is ever stored.
+pub fn generate() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+pub fn hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Compares a presented token against a stored hash without bailing out
+/// early on the first differing byte.
+pub fn verify(token: &str, stored_hash: &str) -> bool {
+    let presented = hash(token);
+
+    if presented.len() != stored_hash.len() {
+        return false;
+    }
+
+    presented
+        .bytes()
+        .zip(stored_hash.bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Cookie holding the delete tokens of the pastes a browser created, as
+/// `id:token` pairs separated by `|`, newest first.
+pub const COOKIE: &str = "delete_tokens";
+
+/// How many pastes [`COOKIE`] remembers, which keeps it well below the
+/// 4 KB browsers allow per cookie even with 64-byte slugs.
+pub const COOKIE_CAPACITY: usize = 20;
+
+/// The token `cookie` holds for `id`.
+pub fn from_cookie<'a>(cookie: &'a str, id: &str) -> Option<&'a str> {
+    cookie
+        .split('|')
+        .filter_map(|pair| pair.split_once(':'))
+        .find(|(key, _)| *key == id)
+        .map(|(_, token)| token)
+}
+
+/// The value of [`COOKIE`] after adding `token` for `id` to `cookie`,
+/// dropping the oldest pastes past [`COOKIE_CAPACITY`].
+pub fn add_to_cookie(cookie: Option<&str>, id: &str, token: &str) -> String {
+    let new = format!("{id}:{token}");
+    let kept = cookie
+        .unwrap_or_default()
+        .split('|')
+        .filter(|pair| matches!(pair.split_once(':'), Some((key, _)) if key != id));
+
+    std::iter::once(new.as_str())
+        .chain(kept)
+        .take(COOKIE_CAPACITY)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_random_and_alphanumeric() {
+        let token = generate();
+        assert_eq!(token.len(), TOKEN_LENGTH);
+        assert!(
+            token.chars().all(|c| c.is_ascii_alphanumeric()),
+            "{}",
+            token
+        );
+        assert_ne!(token, generate());
+    }
+
+    #[test]
+    fn only_the_matching_token_verifies() {
+        assert_eq!(
+            hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let token = generate();
+        let stored = hash(&token);
+        assert!(verify(&token, &stored));
+        assert!(!verify(&generate(), &stored));
+        assert!(!verify(&token, &stored[1..]));
+        assert!(!verify(&token, ""));
+    }
+
+    #[test]
+    fn cookie_keeps_the_newest_tokens() {
+        let mut cookie = add_to_cookie(None, "abc", "t1");
+        assert_eq!(cookie, "abc:t1");
+
+        cookie = add_to_cookie(Some(&cookie), "def", "t2");
+        cookie = add_to_cookie(Some(&cookie), "abc", "t3");
+        assert_eq!(cookie, "abc:t3|def:t2");
+        assert_eq!(from_cookie(&cookie, "def"), Some("t2"));
+        assert_eq!(from_cookie(&cookie, "de"), None);
+
+        for index in 0..COOKIE_CAPACITY {
+            cookie = add_to_cookie(Some(&cookie), &format!("id{index}"), "t");
+        }
+        assert_eq!(cookie.split('|').count(), COOKIE_CAPACITY);
+        assert_eq!(from_cookie(&cookie, "abc"), None);
+        assert_eq!(from_cookie(&cookie, "id0"), Some("t"));
+    }
+}
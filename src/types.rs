@@ -1,24 +1,306 @@
+use anyhow::bail;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(Deserialize, Serialize)]
 pub struct JsonResponse {
     pub message: String,
 }
 
-#[derive(Deserialize, Serialize)]
+/// Body of a `POST /` or `POST /api/pastes` with `Content-Type:
+/// application/json`. Only `content` is required. Fields are kept loose so
+/// that bad values can be reported per field instead of as a parse error.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct CodePasteInput {
+    #[serde(alias = "code")]
+    pub content: Option<String>,
+    /// An extension or a syntax name, see `/api/languages`. Detected when
+    /// left out.
+    pub language: Option<String>,
+    pub filename: Option<String>,
+    /// e.g. `10m`, `1d` or `never`.
+    pub expiry: Option<String>,
+    /// `public` or `unlisted`.
+    pub visibility: Option<String>,
+    pub burn_after_read: bool,
+    pub max_views: Option<u64>,
+    pub slug: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Body of every JSON error. `code` is one of the stable codes of
+/// `error::PasteError`, `errors` is only set for invalid JSON pastes.
+#[derive(Deserialize, Serialize)]
+pub struct ErrorResponse {
     pub code: String,
-    pub language: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
 }
 
+/// Whether a paste may be picked up by search engines. Pastes are never
+/// listed anywhere either way.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Served with `X-Robots-Tag: noindex`.
+    Unlisted,
+}
+
+impl FromStr for Visibility {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "public" => Ok(Visibility::Public),
+            "unlisted" => Ok(Visibility::Unlisted),
+            other => bail!(
+                "unknown visibility '{}', expected public or unlisted",
+                other
+            ),
+        }
+    }
+}
+
+/// The stored paste, minus its content, as returned on creation.
 #[derive(Deserialize, Serialize)]
 pub struct CodePaseResponse {
     pub id: String,
     pub permalink: String,
+    pub delete_token: String,
+    pub language: Option<String>,
+    pub filename: Option<String>,
+    pub created_at: Option<u64>,
+    pub size: usize,
+    pub expires_at: Option<u64>,
+    pub views_remaining: Option<u64>,
+    pub visibility: Visibility,
+}
+
+/// Stored under [`crate::slug::reservation_key`]. Whoever holds the token
+/// matching `owner_token_hash` may publish under the slug.
+#[derive(Deserialize, Serialize)]
+pub struct SlugReservation {
+    pub owner_token_hash: String,
+    pub reserved_at: u64,
+}
+
+/// Upload limits, in bytes, and the longest expiry in seconds (`null`
+/// when pastes may live forever).
+#[derive(Deserialize, Serialize)]
+pub struct Limits {
+    pub max_paste_size: usize,
+    pub max_request_size: usize,
+    pub chunk_size: usize,
+    pub max_expiry: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ThemeInfo {
+    pub id: String,
+    pub name: String,
+    /// Whether the theme has a dark background.
+    pub dark: bool,
+}
+
+/// Served from `/api/themes`. `default`, `light` and `dark` are theme ids,
+/// `default` may also be `auto`.
+#[derive(Deserialize, Serialize)]
+pub struct ThemeList {
+    pub default: String,
+    pub light: String,
+    pub dark: String,
+    pub themes: Vec<ThemeInfo>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LanguageInfo {
+    /// Extension that selects the language as `language` or `?lang=`.
+    pub id: String,
+    pub name: String,
+    pub extensions: Vec<String>,
+    /// Regex matched against the first line of pastes without a known
+    /// extension, e.g. for shebangs.
+    pub first_line: Option<String>,
+}
+
+/// Served from `/api/languages`, sorted by name.
+#[derive(Deserialize, Serialize)]
+pub struct LanguageList {
+    pub languages: Vec<LanguageInfo>,
+}
+
+/// A paste as served to clients asking for JSON.
+#[derive(Deserialize, Serialize)]
+pub struct PasteView {
+    pub id: String,
+    pub content: String,
+    pub language: Option<String>,
+    pub filename: Option<String>,
+    pub created_at: Option<u64>,
+    pub size: usize,
+    pub expires_at: Option<u64>,
+    /// Views left after this one, for view-limited pastes.
+    pub views_remaining: Option<u64>,
+    pub visibility: Visibility,
+}
+
+/// Current layout of [`PasteRecord`]. Version 0 is the legacy format where
+/// the KV value was the bare paste content.
+pub const PASTE_RECORD_VERSION: u32 = 2;
+
+/// The KV value stored under a paste id.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PasteRecord {
+    pub version: u32,
+    pub content: String,
+    pub language: Option<String>,
+    pub filename: Option<String>,
+    pub created_at: Option<u64>,
+    pub size: usize,
+    /// Set for large pastes, whose content lives in this many chunks next
+    /// to the record (see `chunks`) and `content` is left empty. Added in
+    /// version 2.
+    #[serde(default)]
+    pub chunks: Option<u32>,
+}
+
+impl PasteRecord {
+    pub fn new(
+        content: String,
+        language: Option<String>,
+        filename: Option<String>,
+        created_at: u64,
+    ) -> Self {
+        PasteRecord {
+            version: PASTE_RECORD_VERSION,
+            size: content.len(),
+            content,
+            language,
+            filename,
+            created_at: Some(created_at),
+            chunks: None,
+        }
+    }
+
+    /// Reads a stored value, treating anything written before records were
+    /// versioned as version 0 raw content.
+    pub fn decode(value: &str, metadata: Option<&PasteMetadata>) -> serde_json::Result<Self> {
+        match metadata.map(|metadata| metadata.version).unwrap_or(0) {
+            0 => Ok(PasteRecord {
+                version: 0,
+                size: value.len(),
+                content: value.to_string(),
+                language: None,
+                filename: None,
+                created_at: None,
+                chunks: None,
+            }),
+            _ => serde_json::from_str(value),
+        }
+    }
+}
+
+/// Stored as KV metadata next to each paste. Kept small, since KV caps
+/// metadata at 1024 bytes, and enough to list pastes without reading them.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PasteMetadata {
+    #[serde(default)]
+    pub version: u32,
+    pub delete_token_hash: String,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Reads left before the paste deletes itself. `None` means unlimited.
+    #[serde(default)]
+    pub views_remaining: Option<u64>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub size: usize,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Left behind for expiring and view-limited pastes so reads after they're
+/// gone can answer 410 Gone rather than 404.
+#[derive(Deserialize, Serialize)]
+pub struct Tombstone {
+    pub expired_at: u64,
+    /// Missing from tombstones written before pastes could burn.
+    #[serde(default)]
+    pub reason: GoneReason,
+}
+
+/// Why a tombstone was written.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GoneReason {
+    #[default]
+    Expired,
+    /// The paste's last view was spent.
+    Burned,
+}
+
+/// Everything the shared `layout` template wraps around a page.
+#[derive(Serialize)]
+pub struct LayoutContext {
+    pub title: String,
+    /// Generated CSS, inserted unescaped.
+    pub style: Option<String>,
+    pub noindex: bool,
+    /// The rendered page, inserted unescaped.
+    pub body: String,
+}
+
+#[derive(Serialize)]
+pub struct IndexTemplateContext {
+    pub max_length: usize,
+    pub languages: Vec<LanguageInfo>,
+}
+
+#[derive(Serialize)]
+pub struct Link {
+    pub href: String,
+    pub label: String,
 }
 
 #[derive(Serialize)]
 pub struct CodeTemplateContext {
+    /// Highlighted or rendered HTML, inserted unescaped.
     pub code: String,
     pub lang: String,
+    /// Whether `code` is rendered Markdown rather than highlighted source.
+    pub rendered: bool,
+    /// Switches between the rendered and the source view.
+    pub toggle: Option<Link>,
+    pub expires: Option<String>,
+    /// Path the delete button sends `DELETE` to, shown to the browser
+    /// holding the paste's delete token cookie.
+    pub delete: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ConfirmTemplateContext {
+    pub path: String,
+    pub views_remaining: u64,
+    pub warning: String,
+}
+
+#[derive(Serialize)]
+pub struct ErrorTemplateContext {
+    pub status: u16,
+    pub code: String,
+    pub message: String,
 }
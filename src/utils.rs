@@ -1,8 +1,15 @@
+use crate::expiry::format_timestamp;
+use crate::highlight::{
+    highlight, highlight_ansi, is_markdown, languages, syntax_set, theme_css, Colors, LineRanges,
+    ThemeChoice,
+};
+use crate::markdown;
+use crate::templates::Templates;
+use crate::types::{
+    CodeTemplateContext, ConfirmTemplateContext, ErrorTemplateContext, IndexTemplateContext, Link,
+};
 use anyhow::{bail, Result};
 use cfg_if::cfg_if;
-use syntect::highlighting::ThemeSet;
-use syntect::html::highlighted_html_for_string;
-use syntect::parsing::SyntaxSet;
 
 cfg_if! {
     // https://github.com/rustwasm/console_error_panic_hook#readme
@@ -15,519 +22,168 @@ cfg_if! {
     }
 }
 
-pub fn get_code_template() -> String {
-    r#"
-      <html>
-        <head>
-            <title> {lang} code </title>
-        </head>
-        <body>
-            {code}
-        </body>
-    </html>
-    "#
-    .to_string()
+/// Content type for the raw view of a paste with the given extension.
+/// Only types a browser won't render as a document are derived, anything
+/// else (HTML and SVG included) is served as plain text.
+pub fn raw_content_type(ext: Option<&str>) -> mime::Mime {
+    match ext.map(str::to_ascii_lowercase).as_deref() {
+        Some("json") => mime::APPLICATION_JSON,
+        Some("js" | "mjs") => mime::APPLICATION_JAVASCRIPT_UTF_8,
+        Some("css") => mime::TEXT_CSS_UTF_8,
+        Some("csv") => mime::TEXT_CSV_UTF_8,
+        _ => mime::TEXT_PLAIN_UTF_8,
+    }
+}
+
+const ERROR_CSS: &str = "
+body { font-family: sans-serif; max-width: 40em; margin: 4em auto; padding: 0 1em; color: #333; }
+h1 { font-size: 3em; margin-bottom: 0; }
+.code { color: #888; font-family: monospace; }
+";
+
+pub fn get_error_template(
+    templates: &Templates,
+    status: u16,
+    code: &str,
+    message: &str,
+) -> Result<String> {
+    templates.render(
+        "error",
+        &ErrorTemplateContext {
+            status,
+            code: code.to_string(),
+            message: message.to_string(),
+        },
+        &format!("{status} - Priver.dev paste"),
+        Some(ERROR_CSS.to_string()),
+        false,
+    )
 }
 
-pub fn syntax_highlight_code(code: String, lang: String) -> Result<String> {
-    let ss = SyntaxSet::load_defaults_newlines();
-    let ts = ThemeSet::load_defaults();
+/// Shown instead of a paste with a view budget until the reader confirms,
+/// so that link previews don't spend the views.
+pub fn get_confirm_template(
+    templates: &Templates,
+    path: &str,
+    views_remaining: u64,
+) -> Result<String> {
+    let warning = match views_remaining {
+        1 => "This paste will be deleted after you view it.".to_string(),
+        views => format!("This paste can be viewed {views} more times."),
+    };
 
-    let theme = &ts.themes["Solarized (dark)"];
+    templates.render(
+        "confirm",
+        &ConfirmTemplateContext {
+            path: path.to_string(),
+            views_remaining,
+            warning,
+        },
+        "Priver.dev paste",
+        None,
+        true,
+    )
+}
 
-    let sr = match ss.find_syntax_by_extension(lang.as_str()) {
+pub fn syntax_highlight_code(
+    templates: &Templates,
+    code: String,
+    lang: String,
+    expires_at: Option<u64>,
+    theme: &ThemeChoice,
+    marked: &LineRanges,
+    delete: Option<String>,
+) -> Result<String> {
+    let sr = match syntax_set().find_syntax_by_extension(lang.as_str()) {
         Some(code) => code,
         None => bail!("couldn't find syntax with extension: {}", lang),
     };
 
-    let syntax_code = match highlighted_html_for_string(code.as_str(), &ss, sr, theme) {
-        Ok(value) => value,
-        Err(err) => bail!("couldn't syntax higlight code: {}", err),
-    };
+    let highlighted = highlight(code.as_str(), sr, theme, marked)?;
+    let toggle = is_markdown(&lang).then(|| Link {
+        href: "?view=rendered".to_string(),
+        label: "View rendered".to_string(),
+    });
+
+    templates.render(
+        "paste",
+        &CodeTemplateContext {
+            code: highlighted.html,
+            lang: lang.clone(),
+            rendered: false,
+            toggle,
+            expires: expires_at.map(format_timestamp),
+            delete,
+        },
+        &format!("{lang} code"),
+        Some(highlighted.css),
+        false,
+    )
+}
+
+const MARKDOWN_CSS: &str = "
+.markdown { max-width: 50em; margin: 0 auto; font-family: sans-serif; line-height: 1.5; }
+.markdown pre { padding: 1em; overflow-x: auto; }
+.markdown table { border-collapse: collapse; }
+.markdown th, .markdown td { border: 1px solid #ccc; padding: 0.3em 0.6em; }
+.markdown img { max-width: 100%; }
+";
 
-    Ok(get_code_template()
-        .replace("{code}", syntax_code.as_str())
-        .replace("{lang}", lang.as_str()))
+/// The rendered view of a Markdown paste, with fenced code highlighted in
+/// the same theme as the source view.
+pub fn render_markdown(
+    templates: &Templates,
+    source: &str,
+    expires_at: Option<u64>,
+    theme: &ThemeChoice,
+    delete: Option<String>,
+) -> Result<String> {
+    let style = format!("{}{}", MARKDOWN_CSS, theme_css(theme)?.unwrap_or_default());
+
+    templates.render(
+        "paste",
+        &CodeTemplateContext {
+            code: markdown::render(source, theme)?,
+            lang: "md".to_string(),
+            rendered: true,
+            toggle: Some(Link {
+                href: "?view=source".to_string(),
+                label: "View source".to_string(),
+            }),
+            expires: expires_at.map(format_timestamp),
+            delete,
+        },
+        "md code",
+        Some(style),
+        false,
+    )
 }
 
-pub fn get_web_template() -> String {
-    r#"
-    <html>
-    <head>
-        <title> Priver.dev paste </title>
-    </head>
-    <body>
-<form method="post" action="/">
-           <textarea name="content" rows="30" maxlength="393216" \="" placeholder="Paste your code here..." cols="80"></textarea>
-           <br>
-           <label for="language">File Extension:</label>
-           <select name="language">
-                 <option value="Appfile">
-                    Appfile
-                 </option>
-                 <option value="Berksfile">
-                    Berksfile
-                 </option>
-                 <option value="Brewfile">
-                    Brewfile
-                 </option>
-                 <option value="C">
-                    C
-                 </option>
-                 <option value="Cheffile">
-                    Cheffile
-                 </option>
-                 <option value="DOT">
-                    DOT
-                 </option>
-                 <option value="Deliverfile">
-                    Deliverfile
-                 </option>
-                 <option value="Emakefile">
-                    Emakefile
-                 </option>
-                 <option value="Fastfile">
-                    Fastfile
-                 </option>
-                 <option value="GNUmakefile">
-                    GNUmakefile
-                 </option>
-                 <option value="Gemfile">
-                    Gemfile
-                 </option>
-                 <option value="Guardfile">
-                    Guardfile
-                 </option>
-                 <option value="M">
-                    M
-                 </option>
-                 <option value="Makefile">
-                    Makefile
-                 </option>
-                 <option value="OCamlMakefile">
-                    OCamlMakefile
-                 </option>
-                 <option value="PL">
-                    PL
-                 </option>
-                    R
-                 </option>
-                 <option value="Rakefile">
-                    Rakefile
-                 </option>
-                 <option value="Rantfile">
-                    Rantfile
-                 </option>
-                 <option value="Rprofile">
-                    Rprofile
-                 </option>
-                 <option value="S">
-                    S
-                 </option>
-                 <option value="SConscript">
-                    SConscript
-                 </option>
-                 <option value="SConstruct">
-                    SConstruct
-                 </option>
-                 <option value="Scanfile">
-                    Scanfile
-                 </option>
-                 <option value="Sconstruct">
-                    Sconstruct
-                 </option>
-                 <option value="Snakefile">
-                    Snakefile
-                 </option>
-                 <option value="Snapfile">
-                    Snapfile
-                 </option>
-                 <option value="Thorfile">
-                    Thorfile
-                 </option>
-                 <option value="Vagrantfile">
-                    Vagrantfile
-                 </option>
-                 <option value="adp">
-                    adp
-                 </option>
-                 <option value="applescript">
-                    applescript
-                 </option>
-                 <option value="as">
-                    as
-                 </option>
-                 <option value="asa">
-                    asa
-                 </option>
-                 <option value="asp">
-                    asp
-                 </option>
-                 <option value="babel">
-                    babel
-                 </option>
-                 <option value="bash">
-                    bash
-                 </option>
-                 <option value="bat">
-                    bat
-                 </option>
-                 <option value="bib">
-                    bib
-                 </option>
-                 <option value="bsh">
-                    bsh
-                 </option>
-                 <option value="build">
-                    build
-                 </option>
-                 <option value="builder">
-                    builder
-                 </option>
-                 <option value="c">
-                    c
-                 </option>
-                 <option value="c++">
-                    c++
-                 </option>
-                 <option value="capfile">
-                    capfile
-                 </option>
-                 <option value="cc">
-                    cc
-                 </option>
-                 <option value="cgi">
-                    cgi
-                 </option>
-                 <option value="cl">
-                    cl
-                 </option>
-                 <option value="clj">
-                    clj
-                 </option>
-                 <option value="cls">
-                    cls
-                 </option>
-                 <option value="cmd">
-                    cmd
-                 </option>
-                 <option value="config.ru">
-                    config.ru
-                 </option>
-                 <option value="cp">
-                    cp
-                 </option>
-                 <option value="cpp">
-                    cpp
-                 </option>
-                 <option value="cpy">
-                    cpy
-                 </option>
-                 <option value="cs">
-                    cs
-                 </option>
-                 <option value="css">
-                    css
-                 </option>
-                 <option value="css.erb">
-                    css.erb
-                 </option>
-                 <option value="css.liquid">
-                    css.liquid
-                 </option><option value="csx">
-                    csx
-                 </option><option value="cxx">
-                    cxx
-                 </option><option value="d">
-                    d
-                 </option><option value="ddl">
-                    ddl
-                 </option><option value="di">
-                    di
-                 </option><option value="diff">
-                    diff
-                 </option><option value="dml">
-                    dml
-                 </option><option value="dot">
-                    dot
-                 </option><option value="dpr">
-                    dpr
-                 </option><option value="dtml">
-                    dtml
-                 </option><option value="el">
-                    el
-                 </option><option value="emakefile">
-                    emakefile
-                 </option><option value="erb">
-                    erb
-                 </option><option value="erbsql">
-                    erbsql
-                 </option><option value="erl">
-                    erl
-                 </option><option value="es6">
-                    es6
-                 </option><option value="fasl">
-                    fasl
-                 </option><option value="fcgi">
-                    fcgi
-                 </option><option value="gemspec">
-                    gemspec
-                 </option><option value="go">
-                    go
-                 </option><option value="gradle">
-                    gradle
-                 </option><option value="groovy">
-                    groovy
-                 </option><option value="gvy">
-                    gvy
-                 </option><option value="gyp">
-                    gyp
-                 </option><option value="gypi">
-                    gypi
-                 </option><option value="h">
-                    h
-                 </option><option value="h++">
-                    h++
-                 </option><option value="haml">
-                    haml
-                 </option><option value="hh">
-                    hh
-                 </option><option value="hpp">
-                    hpp
-                 </option><option value="hrl">
-                    hrl
-                 </option><option value="hs">
-                    hs
-                 </option><option value="htm">
-                    htm
-                 </option><option value="html">
-                    html
-                 </option><option value="html.erb">
-                    html.erb
-                 </option><option value="hxx">
-                    hxx
-                 </option><option value="inc">
-                    inc
-                 </option><option value="inl">
-                    inl
-                 </option><option value="ipp">
-                    ipp
-                 </option><option value="irbrc">
-                    irbrc
-                 </option><option value="java">
-                    java
-                 </option><option value="jbuilder">
-                    jbuilder
-                 </option><option value="js">
-                    js
-                 </option><option value="js.erb">
-                    js.erb
-                 </option><option value="json">
-                    json
-                 </option><option value="jsp">
-                    jsp
-                 </option><option value="jsx">
-                    jsx
-                 </option><option value="l">
-                    l
-                 </option><option value="lhs">
-                    lhs
-                 </option><option value="lisp">
-                    lisp
-                 </option><option value="lsp">
-                    lsp
-                 </option><option value="ltx">
-                    ltx
-                 </option><option value="lua">
-                    lua
-                 </option><option value="m">
-                    m
-                 </option><option value="mak">
-                    mak
-                 </option><option value="make">
-                    make
-                 </option><option value="makefile">
-                    makefile
-                 </option><option value="markdn">
-                    markdn
-                 </option><option value="markdown">
-                    markdown
-                 </option><option value="matlab">
-                    matlab
-                 </option><option value="md">
-                    md
-                 </option><option value="mdown">
-                    mdown
-                 </option><option value="mk">
-                    mk
-                 </option><option value="ml">
-                    ml
-                 </option><option value="mli">
-                    mli
-                 </option><option value="mll">
-                    mll
-                 </option><option value="mly">
-                    mly
-                 </option><option value="mm">
-                    mm
-                 </option><option value="mud">
-                    mud
-                 </option><option value="opml">
-                    opml
-                 </option><option value="p">
-                    p
-                 </option><option value="pas">
-                    pas
-                 </option><option value="patch">
-                    patch
-                 </option><option value="php">
-                    php
-                 </option><option value="php3">
-                    php3
-                 </option><option value="php4">
-                    php4
-                 </option><option value="php5">
-                    php5
-                 </option><option value="php7">
-                    php7
-                 </option><option value="phps">
-                    phps
-                 </option><option value="phpt">
-                    phpt
-                 </option><option value="phtml">
-                    phtml
-                 </option><option value="pl">
-                    pl
-                 </option><option value="pm">
-                    pm
-                 </option><option value="pod">
-                    pod
-                 </option><option value="podspec">
-                    podspec
-                 </option><option value="prawn">
-                    prawn
-                 </option><option value="properties">
-                    properties
-                 </option><option value="py">
-                    py
-                 </option><option value="py3">
-                    py3
-                 </option><option value="pyi">
-                    pyi
-                 </option><option value="pyw">
-                    pyw
-                 </option><option value="r">
-                    r
-                 </option><option value="rabl">
-                    rabl
-                 </option><option value="rails">
-                    rails
-                 </option><option value="rake">
-                    rake
-                 </option><option value="rb">
-                    rb
-                 </option><option value="rbx">
-                    rbx
-                 </option><option value="rd">
-                    rd
-                 </option><option value="re">
-                    re
-                 </option><option value="rest">
-                    rest
-                 </option><option value="rhtml">
-                    rhtml
-                 </option><option value="rjs">
-                    rjs
-                 </option><option value="rpy">
-                    rpy
-                 </option><option value="rs">
-                    rs
-                 </option><option value="rss">
-                    rss
-                 </option><option value="rst">
-                    rst
-                 </option><option value="ruby.rail">
-                    ruby.rail
-                 </option><option value="rxml">
-                    rxml
-                 </option><option value="s">
-                    s
-                 </option><option value="sass">
-                    sass
-                 </option><option value="sbt">
-                    sbt
-                 </option><option value="scala">
-                    scala
-                 </option><option value="scm">
-                    scm
-                 </option><option value="sconstruct">
-                    sconstruct
-                 </option><option value="sh">
-                    sh
-                 </option><option value="shtml">
-                    shtml
-                 </option><option value="simplecov">
-                    simplecov
-                 </option><option value="sql">
-                    sql
-                 </option><option value="sql.erb">
-                    sql.erb
-                 </option><option value="ss">
-                    ss
-                 </option><option value="sty">
-                    sty
-                 </option><option value="svg">
-                    svg
-                 </option><option value="swift">
-                    swift
-                 </option><option value="t">
-                    t
-                 </option><option value="tcl">
-                    tcl
-                 </option><option value="tex">
-                    tex
-                 </option><option value="textile">
-                    textile
-                 </option><option value="thor">
-                    thor
-                 </option><option value="tld">
-                    tld
-                 </option><option value="tmpl">
-                    tmpl
-                 </option><option value="tpl">
-                    tpl
-                 </option><option value="ts">
-                    ts
-                 </option><option value="tsx">
-                    tsx
-                 </option><option selected="" value="txt">
-                    txt
-                 </option><option value="wscript">
-                    wscript
-                 </option><option value="xhtml">
-                    xhtml
-                 </option><option value="xml">
-                    xml
-                 </option><option value="xsd">
-                    xsd
-                 </option><option value="xslt">
-                    xslt
-                 </option><option value="yaml">
-                    yaml
-                 </option><option value="yaws">
-                    yaws
-                 </option><option value="yml">
-                    yml
-                 </option>
-                 <option value="zsh">
-                    zsh
-                 </option>
-           </select>
-           <br>
-           <input type="submit" value="Paste!">
-        </form>
-        </body>
-        </html>
+/// Terminal counterpart of [`syntax_highlight_code`], without any page
+/// around the code.
+pub fn syntax_highlight_ansi(
+    code: &str,
+    lang: &str,
+    theme: &ThemeChoice,
+    colors: Colors,
+) -> Result<String> {
+    let sr = match syntax_set().find_syntax_by_extension(lang) {
+        Some(code) => code,
+        None => bail!("couldn't find syntax with extension: {}", lang),
+    };
+
+    highlight_ansi(code, sr, theme, colors)
+}
 
-    "#.to_string()
+pub fn get_web_template(templates: &Templates, max_length: usize) -> Result<String> {
+    templates.render(
+        "index",
+        &IndexTemplateContext {
+            max_length,
+            languages: languages(),
+        },
+        "Priver.dev paste",
+        None,
+        false,
+    )
 }
@@ -0,0 +1,463 @@
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::rc::Rc;
+use worker::js_sys::Uint8Array;
+use worker::{FormEntry, Headers, Method, Request, RequestInit, Response};
+
+use crate::{store::PasteStore, types};
+
+/// Multipart and urlencoded fields the handlers understand. `FormData`
+/// can't be iterated, so anything not listed here is ignored.
+pub(crate) const FORM_FIELDS: &[&str] = &[
+    "content",
+    "language",
+    "expiry",
+    "burn_after_read",
+    "max_views",
+    "slug",
+    "visibility",
+];
+
+/// Whether a body sent as `application/x-www-form-urlencoded` really is a
+/// form: every `&`-separated pair is `name=value` with an encoded name, and
+/// nothing in it is a line break or another control character, which forms
+/// encode. `curl --data-binary @file` labels files the same way, and one
+/// that happens to contain `content=` must reach the handler whole rather
+/// than cut down to that field.
+pub(crate) fn is_urlencoded_form(body: &[u8]) -> bool {
+    let encoded = |byte: &u8| byte.is_ascii_alphanumeric() || b"%+-._*".contains(byte);
+
+    body.split(|byte| *byte == b'&')
+        .filter(|pair| !pair.is_empty())
+        .all(|pair| match pair.iter().position(|byte| *byte == b'=') {
+            Some(eq) => {
+                eq > 0 && pair[..eq].iter().all(encoded) && !pair.iter().any(u8::is_ascii_control)
+            }
+            None => false,
+        })
+}
+
+pub enum FormValue {
+    Field(String),
+    File { name: String, bytes: Vec<u8> },
+}
+
+/// A request reduced to plain data, so handlers don't depend on the
+/// Workers runtime.
+#[derive(Default)]
+pub struct Incoming {
+    /// Scheme, host and port the request was sent to, e.g.
+    /// `https://paste.priver.dev`.
+    pub origin: String,
+    pub params: HashMap<String, String>,
+    pub query: Vec<(String, String)>,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub form: HashMap<String, FormValue>,
+    /// The request body, when it was read and isn't already in `form`.
+    pub body: Vec<u8>,
+    /// Set when the body went over the size limit and wasn't read.
+    pub body_too_large: bool,
+}
+
+impl Incoming {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("cookie")?
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The media type of the body, lowercased and without parameters.
+    pub fn content_type(&self) -> Option<String> {
+        let value = self.header("content-type")?;
+        let media_type = value.split(';').next().unwrap_or_default().trim();
+
+        Some(media_type.to_ascii_lowercase())
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.content_type().as_deref() == Some("application/json")
+    }
+
+    /// Whether the body is something the runtime's form parser takes.
+    pub fn is_form(&self) -> bool {
+        matches!(
+            self.content_type().as_deref(),
+            Some("multipart/form-data" | "application/x-www-form-urlencoded")
+        )
+    }
+
+    /// Whether the client prefers a page over JSON, e.g. a browser posting
+    /// the index form.
+    pub fn wants_html(&self) -> bool {
+        self.negotiate(&["application/json", "text/html"]) == Some("text/html")
+    }
+
+    /// Picks one of `offered` based on the `Accept` header, see
+    /// [`negotiate`].
+    pub fn negotiate<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        negotiate(self.header("accept"), offered)
+    }
+
+    /// curl and wget, which get terminal output unless they ask otherwise.
+    pub fn is_terminal(&self) -> bool {
+        self.header("user-agent").map_or(false, |agent| {
+            let agent = agent.to_ascii_lowercase();
+            agent.starts_with("curl/") || agent.starts_with("wget/")
+        })
+    }
+
+    /// A plain form field. File uploads are only reachable through `form`.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match self.form.get(name) {
+            Some(FormValue::Field(value)) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the offered media type the `Accept` header gives the highest
+/// q-value, matching the most specific range for each type. Ties go to
+/// the earlier entry in `offered`, as does a missing header. `None` means
+/// nothing offered is acceptable.
+pub fn negotiate<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let accept = match accept.map(str::trim).filter(|accept| !accept.is_empty()) {
+        Some(accept) => accept,
+        None => return offered.first().copied(),
+    };
+
+    // (type, subtype, q) for every range in the header.
+    let ranges: Vec<(String, String, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            Some((
+                kind.trim().to_ascii_lowercase(),
+                subtype.trim().to_ascii_lowercase(),
+                q,
+            ))
+        })
+        .collect();
+
+    let quality = |offer: &str| {
+        let (kind, subtype) = offer.split_once('/').unwrap_or((offer, ""));
+
+        ranges
+            .iter()
+            .filter_map(|(range_kind, range_subtype, q)| {
+                let specificity = match (range_kind.as_str(), range_subtype.as_str()) {
+                    (k, s) if k == kind && s == subtype => 2,
+                    (k, "*") if k == kind => 1,
+                    ("*", "*") => 0,
+                    _ => return None,
+                };
+                Some((specificity, *q))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, q)| q)
+    };
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for offer in offered {
+        let q = quality(offer);
+        if q > 0.0 && best.map_or(true, |(_, best_q)| q > best_q) {
+            best = Some((offer, q));
+        }
+    }
+
+    best.map(|(offer, _)| offer)
+}
+
+/// What a handler answers with, converted into a `worker::Response` at the
+/// edge of the app.
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// When set, `body` is ignored and the response streams the values of
+    /// these store keys, in order.
+    pub stream: Option<Vec<String>>,
+}
+
+impl Reply {
+    pub fn new(status: u16, content_type: &str, body: String) -> Self {
+        Reply {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+            stream: None,
+        }
+    }
+
+    /// A reply streaming the values of `keys`. It has no `Content-Length`:
+    /// the runtime frames the body, and a length promised up front would be
+    /// wrong if a chunk went missing halfway.
+    pub fn stream(content_type: &str, keys: Vec<String>) -> Self {
+        Reply {
+            stream: Some(keys),
+            ..Reply::new(200, content_type, String::new())
+        }
+    }
+
+    pub fn html(status: u16, body: String) -> Self {
+        Reply::new(status, "text/html", body)
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Reply::new(status, "application/json", body),
+            Err(_) => Reply::message(500, "couldn't serialize response"),
+        }
+    }
+
+    pub fn message(status: u16, message: &str) -> Self {
+        Reply::json(
+            status,
+            &types::JsonResponse {
+                message: message.to_string(),
+            },
+        )
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Reply {
+            status: 302,
+            headers: vec![("Location".to_string(), location.to_string())],
+            body: String::new(),
+            stream: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Reads the request body, or returns `None` as soon as it grows past
+/// `limit` bytes. A `Content-Length` over the limit is rejected before
+/// anything is read.
+///
+/// Uploads are buffered rather than streamed into chunks: the runtime's
+/// form parser wants the whole body, and language detection, the UTF-8
+/// check and the paste record all need the content at once. The limit is
+/// what keeps this within the isolate's memory.
+async fn read_body(req: &mut Request, limit: usize) -> worker::Result<Option<Vec<u8>>> {
+    let declared = req
+        .headers()
+        .get("content-length")?
+        .and_then(|value| value.parse::<usize>().ok());
+
+    if declared.map_or(false, |length| length > limit) {
+        return Ok(None);
+    }
+
+    let mut body = Vec::with_capacity(declared.unwrap_or_default());
+    let mut stream = req.stream()?;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(body))
+}
+
+/// Parses a form out of an already buffered body, by replaying it through
+/// a fresh request so the runtime's multipart parser can be used.
+async fn parse_form(req: &Request, body: &[u8]) -> worker::Result<worker::FormData> {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_headers(req.headers().clone())
+        .with_body(Some(Uint8Array::from(body).into()));
+
+    Request::new_with_init(req.url()?.as_str(), &init)?
+        .form_data()
+        .await
+}
+
+/// Collects everything handlers need from a worker request, with the
+/// `params` its route matched. The body is
+/// only read when `read_form` is set, and never past `max_body` bytes; see
+/// [`Incoming::body_too_large`]. Form bodies are parsed into fields, any
+/// other body is left to the handler.
+pub async fn incoming(
+    req: &mut Request,
+    params: HashMap<String, String>,
+    read_form: bool,
+    max_body: usize,
+) -> worker::Result<Incoming> {
+    let url = req.url()?;
+    let mut incoming = Incoming {
+        origin: url.origin().ascii_serialization(),
+        headers: req
+            .headers()
+            .entries()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .collect(),
+        query: url
+            .query_pairs()
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect(),
+        params,
+        ..Incoming::default()
+    };
+
+    if read_form {
+        let body = match read_body(req, max_body).await? {
+            Some(body) => body,
+            None => {
+                incoming.body_too_large = true;
+                return Ok(incoming);
+            }
+        };
+
+        let urlencoded =
+            incoming.content_type().as_deref() == Some("application/x-www-form-urlencoded");
+        if !incoming.is_form() || (urlencoded && !is_urlencoded_form(&body)) {
+            incoming.body = body;
+            return Ok(incoming);
+        }
+
+        // Raw uploads are often labelled as forms, so a body that doesn't
+        // parse is left for the handler instead of failing.
+        let form = match parse_form(req, &body).await {
+            Ok(form) => form,
+            Err(_) => {
+                incoming.body = body;
+                return Ok(incoming);
+            }
+        };
+
+        // The runtime has its own copy of the form now. Multipart bodies
+        // are let go of before their files are copied out, urlencoded ones
+        // are kept in case they turn out to be a raw upload.
+        match incoming.content_type().as_deref() {
+            Some("multipart/form-data") => drop(body),
+            _ => incoming.body = body,
+        }
+
+        for name in FORM_FIELDS {
+            let value = match form.get(name) {
+                Some(FormEntry::Field(value)) => FormValue::Field(value),
+                Some(FormEntry::File(file)) => FormValue::File {
+                    name: file.name(),
+                    bytes: file.bytes().await?,
+                },
+                None => continue,
+            };
+
+            incoming.form.insert(name.to_string(), value);
+        }
+
+        if incoming.form.contains_key("content") {
+            incoming.body = Vec::new();
+        }
+    }
+
+    Ok(incoming)
+}
+
+/// Converts a reply into a worker response. Streamed replies fetch their
+/// keys from `store` one at a time while the body is being sent.
+pub fn into_response(reply: Reply, store: Rc<dyn PasteStore>) -> worker::Result<Response> {
+    let mut headers = Headers::new();
+    for (name, value) in &reply.headers {
+        headers.append(name, value)?;
+    }
+
+    let res = match reply.stream {
+        Some(keys) => Response::from_stream(stream::iter(keys).then(move |key| {
+            let store = store.clone();
+            async move {
+                match store.get(&key).await {
+                    Ok(Some(stored)) => Ok(stored.value.into_bytes()),
+                    Ok(None) => Err(worker::Error::RustError(format!("missing {key}"))),
+                    Err(err) => Err(worker::Error::RustError(err.to_string())),
+                }
+            }
+        }))?,
+        None => Response::ok(reply.body)?,
+    };
+
+    Ok(res.with_status(reply.status).with_headers(headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFERED: &[&str] = &["text/html", "application/json", "text/plain"];
+
+    #[test]
+    fn only_whole_forms_are_urlencoded_forms() {
+        assert!(is_urlencoded_form(
+            b"content=fn+main%28%29+%7B%7D&language=rs"
+        ));
+        assert!(is_urlencoded_form(b"content=hello world&expiry=1d&"));
+        assert!(is_urlencoded_form(b""));
+        assert!(is_urlencoded_form(b"content=x&csrf_token=abc"));
+
+        // `curl --data-binary @file` with whatever the file holds.
+        assert!(!is_urlencoded_form(b"print('a=b')\n"));
+        assert!(!is_urlencoded_form(b"x = 1\n&content=gotcha"));
+        assert!(!is_urlencoded_form(b"content=line one\nline two\n"));
+        assert!(!is_urlencoded_form(b"content=a&b"));
+        assert!(!is_urlencoded_form(b"=x&content=y"));
+    }
+
+    #[test]
+    fn missing_accept_takes_the_first_offer() {
+        assert_eq!(negotiate(None, OFFERED), Some("text/html"));
+        assert_eq!(negotiate(Some("*/*"), OFFERED), Some("text/html"));
+    }
+
+    #[test]
+    fn highest_q_value_wins() {
+        let accept = "text/html;q=0.5, application/json, text/*;q=0.8";
+
+        assert_eq!(negotiate(Some(accept), OFFERED), Some("application/json"));
+        assert_eq!(
+            negotiate(Some("text/*;q=0.9, text/html;q=0.1"), OFFERED),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn excluded_types_are_not_acceptable() {
+        assert_eq!(
+            negotiate(Some("text/html;q=0, */*;q=0.1"), OFFERED),
+            Some("application/json")
+        );
+        assert_eq!(negotiate(Some("image/png"), OFFERED), None);
+    }
+}